@@ -0,0 +1,185 @@
+use crate::date::TaskDate;
+use crate::duration::{format_duration, format_span, parse_duration};
+use crate::task::property_param;
+use crate::zone::Zone;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use ical::property::Property;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Anchor {
+    Start,
+    Due,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Alarm {
+    // seconds from the task's start or due date, negative being before it
+    Relative(Anchor, i64),
+    Absolute(DateTime<Utc>),
+}
+
+impl Alarm {
+    // Reads the value and parameters of a VALARM TRIGGER
+    pub fn from_trigger(property: &Property) -> Option<Self> {
+        let value = property.value.as_deref()?.trim();
+        let param = |name: &str| property_param(property, name).map(str::to_uppercase);
+        if param("VALUE").as_deref() == Some("DATE-TIME") {
+            return NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")
+                .ok()
+                .map(|time| Alarm::Absolute(DateTime::from_utc(time, Utc)));
+        }
+        let anchor = match param("RELATED").as_deref() {
+            Some("END") => Anchor::Due,
+            _ => Anchor::Start,
+        };
+        Some(Alarm::Relative(anchor, parse_duration(value)?))
+    }
+
+    pub fn to_trigger(&self) -> Property {
+        let (params, value) = match self {
+            Alarm::Relative(Anchor::Start, offset) => (None, format_duration(*offset)),
+            Alarm::Relative(Anchor::Due, offset) => (
+                Some(vec![("RELATED".to_owned(), vec!["END".to_owned()])]),
+                format_duration(*offset),
+            ),
+            Alarm::Absolute(time) => (
+                Some(vec![("VALUE".to_owned(), vec!["DATE-TIME".to_owned()])]),
+                time.format("%Y%m%dT%H%M%SZ").to_string(),
+            ),
+        };
+        Property {
+            name: "TRIGGER".to_owned(),
+            params,
+            value: Some(value),
+        }
+    }
+
+    // When the alarm goes off, or None if it is relative to a date the task
+    // does not have
+    pub fn time(
+        &self,
+        start: Option<&TaskDate>,
+        due: Option<&TaskDate>,
+        zone: &Zone,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            Alarm::Relative(Anchor::Start, offset) => {
+                Some(start?.to_utc(zone) + Duration::seconds(*offset))
+            }
+            Alarm::Relative(Anchor::Due, offset) => {
+                Some(due?.to_utc(zone) + Duration::seconds(*offset))
+            }
+            Alarm::Absolute(time) => Some(*time),
+        }
+    }
+
+    pub fn shifted(&self, by: Duration) -> Self {
+        match self {
+            Alarm::Absolute(time) => Alarm::Absolute(*time + by),
+            relative => relative.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Alarm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let anchor = |anchor: &Anchor| match anchor {
+            Anchor::Start => "start",
+            Anchor::Due => "due",
+        };
+        match self {
+            Alarm::Relative(to, 0) => write!(f, "at {}", anchor(to)),
+            Alarm::Relative(to, offset) if *offset < 0 => {
+                write!(f, "{} before {}", format_span(-offset), anchor(to))
+            }
+            Alarm::Relative(to, offset) => {
+                write!(f, "{} after {}", format_span(*offset), anchor(to))
+            }
+            Alarm::Absolute(time) => write!(f, "at {} UTC", time.format("%Y-%m-%d %H:%M")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::from_utc(
+            NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap(),
+            Utc,
+        )
+    }
+
+    fn trigger(params: &[(&str, &str)], value: &str) -> Property {
+        Property {
+            name: "TRIGGER".to_owned(),
+            params: match params.is_empty() {
+                true => None,
+                false => Some(
+                    params
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), vec![value.to_string()]))
+                        .collect(),
+                ),
+            },
+            value: Some(value.to_owned()),
+        }
+    }
+
+    #[test]
+    fn reads_triggers() {
+        assert_eq!(
+            Alarm::from_trigger(&trigger(&[], "-PT15M")),
+            Some(Alarm::Relative(Anchor::Start, -900))
+        );
+        assert_eq!(
+            Alarm::from_trigger(&trigger(&[("RELATED", "END")], "PT1H")),
+            Some(Alarm::Relative(Anchor::Due, 3600))
+        );
+        assert_eq!(
+            Alarm::from_trigger(&trigger(&[("VALUE", "date-time")], "20240105T090000Z")),
+            Some(Alarm::Absolute(utc("2024-01-05 09:00")))
+        );
+        assert_eq!(Alarm::from_trigger(&trigger(&[], "soon")), None);
+    }
+
+    #[test]
+    fn triggers_round_trip() {
+        for alarm in [
+            Alarm::Relative(Anchor::Start, 0),
+            Alarm::Relative(Anchor::Start, -900),
+            Alarm::Relative(Anchor::Due, -86400),
+            Alarm::Absolute(utc("2024-01-05 09:00")),
+        ] {
+            let trigger = alarm.to_trigger();
+            assert_eq!(trigger.name, "TRIGGER");
+            assert_eq!(Alarm::from_trigger(&trigger), Some(alarm));
+        }
+    }
+
+    #[test]
+    fn goes_off_relative_to_its_date() {
+        let start = TaskDate::Utc(utc("2024-01-05 09:00"));
+        let due = TaskDate::Utc(utc("2024-01-06 17:00"));
+        let zone = Zone::utc();
+        assert_eq!(
+            Alarm::Relative(Anchor::Start, -900).time(Some(&start), Some(&due), &zone),
+            Some(utc("2024-01-05 08:45"))
+        );
+        assert_eq!(
+            Alarm::Relative(Anchor::Due, 3600).time(Some(&start), Some(&due), &zone),
+            Some(utc("2024-01-06 18:00"))
+        );
+        assert_eq!(
+            Alarm::Relative(Anchor::Due, 0).time(Some(&start), None, &zone),
+            None
+        );
+        assert_eq!(
+            Alarm::Absolute(utc("2024-01-01 12:00")).time(None, None, &zone),
+            Some(utc("2024-01-01 12:00"))
+        );
+    }
+}
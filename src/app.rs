@@ -1,9 +1,22 @@
-use crate::task::{self, Task, TimeOfDay, Weather};
-use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use crate::alarm::Alarm;
+use crate::config::TagMapping;
+use crate::date::TaskDate;
+use crate::duration::format_span;
+use crate::error::{Error, Result};
+use crate::journal::{Journal, JournalEntry};
+use crate::recurrence::Recurrence;
+use crate::task::{self, Priority, Task, TaskItem, TimeOfDay, Weather};
+use crate::zone::Zone;
+use chrono::{DateTime, Utc};
 use kitchen_fridge::{
-    calendar::cached_calendar::CachedCalendar,
-    traits::{CalDavSource, CompleteCalendar, DavCalendar},
-    CalDavProvider, Item,
+    calendar::cached_calendar::CachedCalendar, item::SyncStatus, CalDavProvider, Item,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::Deref,
+    sync::{Arc, Mutex},
 };
 use url::Url;
 use uuid::Uuid;
@@ -11,227 +24,938 @@ use uuid::Uuid;
 pub struct App {
     pub provider: CalDavProvider,
     pub source_url: Url,
-    tasks: Vec<Task>,
+    pub zone: Arc<Zone>,
+    pub device: String,
+    pub tag_mapping: TagMapping,
+    next_sequence: u64,
+    quarantined: Vec<Quarantined>,
+    state: State,
     events: Log,
+    journal: Journal,
 }
 
 impl App {
-    pub async fn new(mut provider: CalDavProvider, source_url: Url) -> Self {
-        provider.sync().await;
-        let local_calendar = provider.local().get_calendar(&source_url).await.unwrap();
-        let local_calendar = &*local_calendar.lock().unwrap();
-        let items: Vec<Item> = local_calendar
-            .get_items()
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|(_, item)| item.clone())
-            .collect();
-        let tasks = get_tasks_from_items(items).await;
-        Self {
+    pub async fn new(
+        provider: CalDavProvider,
+        source_url: Url,
+        zone: Arc<Zone>,
+        device: String,
+        tag_mapping: TagMapping,
+        journal: Journal,
+    ) -> Result<Self> {
+        let mut app = Self {
             provider,
             source_url,
-            tasks,
+            zone,
+            device,
+            tag_mapping,
+            next_sequence: 1,
+            quarantined: Vec::new(),
+            state: State::new(Vec::new()),
             events: Log::new(),
+            journal,
+        };
+        app.reload().await?;
+        Ok(app)
+    }
+
+    // Fetches the server's latest state and replays the journal over it,
+    // picking up changes made since by other processes
+    pub async fn reload(&mut self) -> Result<()> {
+        // a failed sync is fine while the calendar is cached, as the server
+        // may just be unreachable for now
+        let synced = self.provider.sync().await;
+        match self.refresh() {
+            Err(Error::CalendarNotFound(url)) if !synced => Err(Error::Provider(format!(
+                "sync did not complete and {} is not cached",
+                url
+            ))),
+            refreshed => refreshed,
+        }
+    }
+
+    // Replays the journal over the local calendar without going to the
+    // server, picking up the changes other processes have not synced yet
+    pub fn refresh(&mut self) -> Result<()> {
+        let calendar = self.local_calendar()?;
+        let items: Vec<Item> = calendar
+            .lock()?
+            .get_items_sync()
+            .map_err(|error| Error::Provider(error.to_string()))?
+            .into_values()
+            // deletions that could not be sent yet are still cached
+            .filter(|item| !matches!(item.sync_status(), SyncStatus::LocallyDeleted(_)))
+            .cloned()
+            .collect();
+        let (tasks, quarantined) = get_tasks_from_items(items);
+        let tasks = tasks
+            .iter()
+            .map(|task| task.with_mapped_tags(&self.tag_mapping))
+            .collect();
+        self.next_sequence = 1;
+        self.quarantined = quarantined;
+        self.state = State::new(tasks);
+        self.events = Log::new();
+        for entry in self.journal.read()? {
+            match entry {
+                JournalEntry::Add(event) => self.record(*event),
+                JournalEntry::Back => self.step_back(),
+                JournalEntry::Forward => self.step_forward(),
+                JournalEntry::Checkpoint(next_sequence) => self.next_sequence = next_sequence,
+            }
+        }
+        Ok(())
+    }
+
+    // Applies the event to the tasks, leaving them as they were if it fails.
+    // Gives each task it changed as it was before.
+    fn update(
+        tasks: &mut HashMap<Uuid, Task>,
+        event: &Message,
+    ) -> std::result::Result<HashMap<Uuid, Option<Task>>, ValidationError> {
+        let mut changes = ChangeSet::new(tasks);
+        match App::apply_event(&mut changes, event) {
+            Ok(()) => Ok(changes.before),
+            Err(error) => {
+                changes.restore();
+                Err(error)
+            }
         }
     }
 
-    fn update(tasks: &[Task], event: Message) -> Vec<Task> {
+    fn apply_event(
+        tasks: &mut ChangeSet,
+        event: &Message,
+    ) -> std::result::Result<(), ValidationError> {
         //this will match on the event and make a change depending on it
         match event {
             Message::SetName(task_id, name) => {
-                App::perform_action(tasks, task_id, |t: Task| t.set_name(name.clone()))
+                App::perform_action(tasks, *task_id, |t: &Task| t.set_name(name.clone()))
+            }
+            Message::SetDescription(task_id, description) => {
+                App::perform_action(tasks, *task_id, |t: &Task| {
+                    t.set_description(description.clone())
+                })
             }
-            Message::MarkComplete(task_id) => {
-                App::perform_action(tasks, task_id, |t: Task| t.mark_complete())
+            Message::MarkComplete(task_id, policy) => {
+                App::perform_action(tasks, *task_id, |t: &Task| t.mark_complete())?;
+                App::schedule_next_instance(tasks, *task_id);
+                if policy.complete_subtree {
+                    for id in App::subtree_ids(tasks, *task_id) {
+                        if tasks.get(&id).is_some_and(|t| !t.is_closed()) {
+                            App::perform_action(tasks, id, |t: &Task| t.mark_complete())?;
+                            App::schedule_next_instance(tasks, id);
+                        }
+                    }
+                }
+                if policy.complete_parent {
+                    App::complete_finished_parents(tasks, *task_id);
+                }
+                Ok(())
             }
             Message::MarkIncomplete(task_id) => {
-                App::perform_action(tasks, task_id, |t: Task| t.mark_incomplete())
+                App::perform_action(tasks, *task_id, |t: &Task| t.mark_incomplete())
+            }
+            Message::MarkInProcess(task_id) => {
+                App::perform_action(tasks, *task_id, |t: &Task| t.mark_in_process())
+            }
+            Message::MarkCancelled(task_id) => {
+                App::perform_action(tasks, *task_id, |t: &Task| t.mark_cancelled())?;
+                App::schedule_next_instance(tasks, *task_id);
+                Ok(())
+            }
+            Message::SetPercentComplete(task_id, percent_complete) => {
+                App::perform_action(tasks, *task_id, |t: &Task| {
+                    t.set_percent_complete(*percent_complete)
+                })
             }
             Message::SetStartDate(task_id, start_date) => {
-                App::perform_action(tasks, task_id, |t: Task| t.set_start_date(start_date))
+                App::perform_action(tasks, *task_id, |t: &Task| {
+                    t.set_start_date(start_date.clone())
+                })
             }
             Message::SetDueDate(task_id, due_date) => {
-                App::perform_action(tasks, task_id, |t: Task| t.set_due_date(due_date))
+                App::perform_action(tasks, *task_id, |t: &Task| t.set_due_date(due_date.clone()))
             }
             Message::AddContext(task_id, context) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
                     t.add_context(context.clone())
                 })
             }
             Message::RemoveContext(task_id, context) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
                     t.remove_context(context.clone())
                 })
             }
             Message::AddProject(task_id, project) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
                     t.add_project(project.clone())
                 })
             }
             Message::RemoveProject(task_id, project) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
                     t.remove_project(project.clone())
                 })
             }
             Message::AddArea(task_id, area) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
                     t.add_area(area.clone())
                 })
             }
             Message::RemoveArea(task_id, area) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
                     t.remove_area(area.clone())
                 })
             }
+            Message::AddTag(task_id, tag) => {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
+                    t.add_tag(tag.clone())
+                })
+            }
+            Message::RemoveTag(task_id, tag) => {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
+                    t.remove_tag(tag.clone())
+                })
+            }
+            Message::SetPriority(task_id, priority) => {
+                App::perform_action(tasks, *task_id, |t: &Task| t.set_priority(*priority))
+            }
+            Message::SetEstimate(task_id, estimate) => {
+                App::perform_action(tasks, *task_id, |t: &Task| t.set_estimate(*estimate))
+            }
+            Message::StartTimer(task_id, at) => {
+                if tasks
+                    .get(task_id)
+                    .is_some_and(|task| task.timer_started().is_some())
+                {
+                    return Err(ValidationError::TimerRunning(*task_id));
+                }
+                App::perform_action(tasks, *task_id, |t: &Task| t.start_timer(*at))
+            }
+            Message::StopTimer(task_id, at) => {
+                if tasks
+                    .get(task_id)
+                    .is_some_and(|task| task.timer_started().is_none())
+                {
+                    return Err(ValidationError::TimerNotRunning(*task_id));
+                }
+                App::perform_action(tasks, *task_id, |t: &Task| t.stop_timer(*at))
+            }
+            Message::AddAlarm(task_id, alarm) => {
+                App::perform_action(tasks, *task_id, |t: &Task| t.add_alarm(alarm.clone()))
+            }
+            Message::RemoveAlarm(task_id, alarm) => {
+                App::perform_action(tasks, *task_id, |t: &Task| t.remove_alarm(alarm))
+            }
             Message::SetMoneyNeeded(task_id, money_needed) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
-                    t.set_money_needed(money_needed)
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
+                    t.set_money_needed(*money_needed)
                 })
             }
             Message::SetWeather(task_id, weather) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
                     t.set_weather(weather.clone())
                 })
             }
             Message::SetTimeOfDay(task_id, time_of_day) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
                     t.set_time_of_day(time_of_day.clone())
                 })
             }
+            Message::SetRecurrence(task_id, recurrence) => {
+                App::perform_action(tasks, *task_id, |t: &Task| {
+                    t.set_recurrence(recurrence.clone())
+                })
+            }
             Message::SetParentTask(task_id, parent_task_id) => {
-                App::perform_action(tasks, task_id, |t: Task| -> Task {
-                    t.set_parent_task(parent_task_id)
+                if let Some(parent_task_id) = parent_task_id {
+                    App::check_parent(tasks, *task_id, *parent_task_id)?;
+                }
+                App::perform_action(tasks, *task_id, |t: &Task| -> Task {
+                    t.set_parent_task(*parent_task_id)
+                })
+            }
+            Message::AddDependency(task_id, dependency_id) => {
+                App::check_dependency(tasks, *task_id, *dependency_id)?;
+                App::perform_action(tasks, *task_id, |t: &Task| t.add_dependency(*dependency_id))
+            }
+            Message::RemoveDependency(task_id, dependency_id) => {
+                App::perform_action(tasks, *task_id, |t: &Task| {
+                    t.remove_dependency(*dependency_id)
                 })
             }
             Message::AddTask(task) => {
-                let mut tasks = Vec::from(tasks);
-                tasks.push(task);
-                tasks
-            }
-            Message::RemoveTask(task_to_remove_id) => {
-                let tasks = Vec::from(tasks);
-                tasks
-                    .clone()
+                if tasks.contains_key(&task.id) {
+                    return Err(ValidationError::DuplicateTask(task.id));
+                }
+                if let Some(parent_task_id) = task.parent_task() {
+                    App::check_parent(tasks, task.id, parent_task_id)?;
+                }
+                tasks.insert(task.id, (**task).clone());
+                Ok(())
+            }
+            Message::RemoveTask(task_to_remove_id, policy) => {
+                let removed = tasks
+                    .get(task_to_remove_id)
+                    .cloned()
+                    .ok_or(ValidationError::NoSuchTask(*task_to_remove_id))?;
+                let before: Vec<Uuid> = tasks.keys().copied().collect();
+                let children: Vec<Uuid> = tasks
+                    .values()
+                    .filter(|task| task.parent_task() == Some(*task_to_remove_id))
+                    .map(|task| task.id)
+                    .collect();
+                match policy {
+                    RemovePolicy::Refuse if !children.is_empty() => {
+                        return Err(ValidationError::HasSubtasks(*task_to_remove_id))
+                    }
+                    RemovePolicy::Refuse => {}
+                    RemovePolicy::DeleteSubtree => {
+                        for id in App::subtree_ids(tasks, *task_to_remove_id) {
+                            tasks.remove(&id);
+                        }
+                    }
+                    RemovePolicy::Reparent => {
+                        for id in children {
+                            App::perform_action(tasks, id, |t: &Task| {
+                                t.set_parent_task(removed.parent_task())
+                            })?;
+                        }
+                    }
+                }
+                tasks.remove(task_to_remove_id);
+                // dependencies on the removed tasks go with them
+                let removed_ids: Vec<Uuid> = before
                     .into_iter()
-                    .filter(|task| task_to_remove_id != task.id)
-                    .collect()
+                    .filter(|id| !tasks.contains_key(id))
+                    .collect();
+                let dependents: Vec<Uuid> = tasks
+                    .values()
+                    .filter(|task| task.depends_on().iter().any(|id| removed_ids.contains(id)))
+                    .map(|task| task.id)
+                    .collect();
+                for task_id in dependents {
+                    App::perform_action(tasks, task_id, |t: &Task| {
+                        removed_ids.iter().fold(t.clone(), |task, id| {
+                            match task.depends_on().contains(id) {
+                                true => task.remove_dependency(*id),
+                                false => task,
+                            }
+                        })
+                    })?;
+                }
+                Ok(())
+            }
+            Message::Batch(events) => {
+                // every task the batch changed is restored if any message fails
+                for event in events {
+                    App::apply_event(tasks, event)?;
+                }
+                Ok(())
             }
         }
     }
 
-    pub fn perform_action<F>(tasks: &[Task], task_to_change_id: Uuid, mut action: F) -> Vec<Task>
+    fn perform_action<F>(
+        tasks: &mut ChangeSet,
+        task_to_change_id: Uuid,
+        action: F,
+    ) -> std::result::Result<(), ValidationError>
     where
-        F: FnMut(Task) -> Task,
+        F: FnOnce(&Task) -> Task,
     {
-        tasks
-            .into_iter()
-            .map(|task| {
-                if task.id == task_to_change_id {
-                    action(task.clone())
-                } else {
-                    task.clone()
+        match tasks.get_mut(&task_to_change_id) {
+            Some(task) => {
+                *task = action(task);
+                Ok(())
+            }
+            None => Err(ValidationError::NoSuchTask(task_to_change_id)),
+        }
+    }
+
+    // Adds the next instance of a recurring task that has just been completed
+    // or cancelled, leaving the closed one as a plain task
+    fn schedule_next_instance(tasks: &mut ChangeSet, task_id: Uuid) {
+        let task = match tasks.get(&task_id) {
+            Some(task) => task,
+            None => return,
+        };
+        let completed_at = task.completion_date().unwrap_or(task.last_modified());
+        if let Some(next) = task.next_instance(completed_at) {
+            let task = task.set_recurrence(None);
+            tasks.insert(task_id, task);
+            if !tasks.contains_key(&next.id) {
+                tasks.insert(next.id, next);
+            }
+        }
+    }
+
+    // every task below task_id, in no particular order
+    fn subtree_ids(tasks: &HashMap<Uuid, Task>, task_id: Uuid) -> Vec<Uuid> {
+        let mut subtree: Vec<Uuid> = Vec::new();
+        let mut parents = vec![task_id];
+        while let Some(parent_id) = parents.pop() {
+            for task in tasks.values() {
+                if task.parent_task() == Some(parent_id)
+                    && task.id != task_id
+                    && !subtree.contains(&task.id)
+                {
+                    subtree.push(task.id);
+                    parents.push(task.id);
                 }
-            })
-            .collect()
+            }
+        }
+        subtree
+    }
+
+    // completes each ancestor of task_id whose subtasks are now all complete
+    fn complete_finished_parents(tasks: &mut ChangeSet, task_id: Uuid) {
+        let mut child_id = task_id;
+        while let Some(parent_id) = tasks.get(&child_id).and_then(|task| task.parent_task()) {
+            if parent_id == task_id {
+                break;
+            }
+            let finished = tasks
+                .values()
+                .filter(|task| task.parent_task() == Some(parent_id))
+                .all(|task| task.is_closed());
+            match tasks.get(&parent_id) {
+                Some(parent) if finished && !parent.is_closed() => {
+                    let parent = parent.mark_complete();
+                    tasks.insert(parent_id, parent);
+                    App::schedule_next_instance(tasks, parent_id);
+                }
+                _ => break,
+            }
+            child_id = parent_id;
+        }
+    }
+
+    // A task cannot depend on itself, directly or through other tasks
+    fn check_dependency(
+        tasks: &HashMap<Uuid, Task>,
+        task_id: Uuid,
+        dependency_id: Uuid,
+    ) -> std::result::Result<(), ValidationError> {
+        if !tasks.contains_key(&dependency_id) {
+            return Err(ValidationError::NoSuchTask(dependency_id));
+        }
+        let mut pending = vec![dependency_id];
+        let mut visited = Vec::new();
+        while let Some(id) = pending.pop() {
+            if id == task_id {
+                return Err(ValidationError::DependencyCycle(task_id, dependency_id));
+            }
+            if visited.contains(&id) {
+                continue;
+            }
+            visited.push(id);
+            if let Some(task) = tasks.get(&id) {
+                pending.extend(task.depends_on().iter().copied());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_parent(
+        tasks: &HashMap<Uuid, Task>,
+        task_id: Uuid,
+        parent_task_id: Uuid,
+    ) -> std::result::Result<(), ValidationError> {
+        let mut ancestor_id = Some(parent_task_id);
+        let mut visited = Vec::new();
+        while let Some(id) = ancestor_id {
+            // an existing cycle further up, e.g. from another client, ends the walk
+            if visited.contains(&id) {
+                break;
+            }
+            visited.push(id);
+            if id == task_id {
+                return Err(ValidationError::ParentCycle(task_id, parent_task_id));
+            }
+            ancestor_id = match tasks.get(&id) {
+                Some(ancestor) => ancestor.parent_task(),
+                None if id == parent_task_id => {
+                    return Err(ValidationError::NoSuchParent(parent_task_id))
+                }
+                None => None,
+            };
+        }
+        Ok(())
     }
 
     pub fn get_present_state(&self) -> Vec<Task> {
-        //this will loop through all the events and update
-        self.events
-            .clone()
-            .prev
-            .into_iter()
-            .fold(self.clone().tasks.clone(), |current, event| -> Vec<Task> {
-                App::update(&current, event)
+        let mut tasks: Vec<Task> = self.state.current.values().cloned().collect();
+        tasks.sort_by(|a, b| {
+            a.creation_date()
+                .cmp(&b.creation_date())
+                .then(a.id.cmp(&b.id))
+        });
+        tasks
+    }
+
+    pub fn get_task(&self, task_id: Uuid) -> Option<&Task> {
+        self.state.current.get(&task_id)
+    }
+
+    pub fn tree(&self) -> Tree<'_> {
+        Tree::new(&self.state.current)
+    }
+
+    // the parent of task_id, then its parent, and so on up to a root
+    pub fn ancestors(&self, task_id: Uuid) -> Vec<Task> {
+        let mut ancestors: Vec<Task> = Vec::new();
+        let mut parent_id = self.get_task(task_id).and_then(|task| task.parent_task());
+        while let Some(id) = parent_id {
+            if id == task_id || ancestors.iter().any(|ancestor| ancestor.id == id) {
+                break;
+            }
+            match self.get_task(id) {
+                Some(parent) => {
+                    parent_id = parent.parent_task();
+                    ancestors.push(parent.clone());
+                }
+                None => break,
+            }
+        }
+        ancestors
+    }
+
+    // A task is blocked while any task it depends on is still open. Links to
+    // tasks that are not in the calendar do not block.
+    pub fn is_blocked(&self, task_id: Uuid) -> bool {
+        self.state.current.get(&task_id).is_some_and(|task| {
+            task.depends_on().iter().any(|id| {
+                self.state
+                    .current
+                    .get(id)
+                    .is_some_and(|dependency| !dependency.is_closed())
             })
+        })
+    }
+
+    pub fn quarantined(&self) -> &[Quarantined] {
+        &self.quarantined
+    }
+
+    fn record(&mut self, entry: LogEntry) {
+        // a replayed event may no longer apply if the server changed since it
+        // was recorded, but it keeps its place in the log so undo stays aligned
+        self.next_sequence = entry.sequence + 1;
+        let _ = self.state.apply(&entry.message, self.events.prev.len() + 1);
+        self.events.add(entry);
+    }
+
+    fn step_back(&mut self) {
+        self.events.back();
+        self.state.rewind(&self.events.prev);
+    }
+
+    fn step_forward(&mut self) {
+        if let Some(entry) = self.events.upcoming() {
+            let _ = self.state.apply(&entry.message, self.events.prev.len() + 1);
+            self.events.forward();
+        }
+    }
+
+    pub fn new_event(&mut self, event: Message) -> Result<()> {
+        self.state.apply(&event, self.events.prev.len() + 1)?;
+        let entry = LogEntry {
+            sequence: self.next_sequence,
+            timestamp: Utc::now(),
+            device: self.device.clone(),
+            message: event,
+        };
+        // the event is checked against the state before it is journaled, so
+        // the state goes back to how it was if journaling it fails
+        if let Err(error) = self
+            .journal
+            .append(&JournalEntry::Add(Box::new(entry.clone())))
+        {
+            self.state.rewind(&self.events.prev);
+            return Err(error.into());
+        }
+        self.next_sequence += 1;
+        self.events.add(entry);
+        Ok(())
+    }
+
+    pub fn undo(&mut self) -> Result<Option<LogEntry>> {
+        let event = match self.events.last() {
+            Some(event) => event.clone(),
+            None => return Ok(None),
+        };
+        self.journal.append(&JournalEntry::Back)?;
+        self.step_back();
+        Ok(Some(event))
+    }
+
+    pub fn redo(&mut self) -> Result<Option<LogEntry>> {
+        let event = match self.events.upcoming() {
+            Some(event) => event.clone(),
+            None => return Ok(None),
+        };
+        self.journal.append(&JournalEntry::Forward)?;
+        self.step_forward();
+        Ok(Some(event))
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.events.last().is_some()
     }
 
-    pub fn new_event(&mut self, event: Message) {
-        self.events.add(event);
+    pub fn can_redo(&self) -> bool {
+        self.events.upcoming().is_some()
     }
 
-    pub async fn sync(&mut self) {
-        let current_items = self.get_local_calendar_items().await;
-        let tasks = self.get_present_state();
+    pub fn undo_description(&self) -> Option<String> {
+        self.events.last().map(|entry| entry.message.to_string())
+    }
+
+    pub fn redo_description(&self) -> Option<String> {
+        self.events
+            .upcoming()
+            .map(|entry| entry.message.to_string())
+    }
+
+    pub async fn sync(&mut self) -> Result<()> {
+        let calendar = self.local_calendar()?;
+        let current_items: Vec<Item> = calendar
+            .lock()?
+            .get_items_sync()
+            .map_err(|error| Error::Provider(error.to_string()))?
+            .into_values()
+            .cloned()
+            .collect();
+        let mut tasks = self.get_present_state();
         for item in current_items.iter() {
-            if tasks.iter().any(|task| task.id.to_string() == item.uid()) {
-                println!("Updating Task {}", item.uid());
-                self.provider
-                    .local_mut()
-                    .get_calendar(&self.source_url)
-                    .await
-                    .expect("Failed to get calendar")
-                    .lock()
-                    .expect("failed to unlock calendar")
-                    .add_item_sync(
-                        tasks
-                            .iter()
-                            .find(|task| task.id.to_string() == item.uid())
-                            .expect("failed to get task")
-                            .to_item(&self.source_url),
-                    )
-                    .expect("Failed to update item");
-            } else {
-                self.provider
-                    .local_mut()
-                    .get_calendar(&self.source_url)
-                    .await
-                    .expect("Failed to get calendar")
-                    .lock()
-                    .expect("failed to unlock calendar")
-                    .mark_for_deletion_sync(item.url())
-                    .expect("Failed to update item");
-            }
-        }
-
-        for task in tasks.iter() {
-            if !current_items
+            // items that could not be read are left for their own clients
+            if self
+                .quarantined
                 .iter()
-                .any(|item| task.id.to_string() == item.uid())
+                .any(|quarantined| &quarantined.url == item.url())
             {
-                println!("Adding task {}", &task.id.to_string());
-                self.provider
-                    .local_mut()
-                    .get_calendar(&self.source_url)
-                    .await
-                    .expect("Failed to get calendar")
-                    .lock()
-                    .expect("failed to unlock calendar")
-                    .add_item_sync(task.to_item(&self.source_url))
-                    .expect("Failed to update item");
-            }
-        }
-        self.provider.sync().await;
-    }
-
-    async fn get_local_calendar(&mut self) -> CachedCalendar {
-        let local_calendar = self
-            .provider
-            .local_mut()
-            .get_calendar(&self.source_url)
-            .await
-            .unwrap();
-        let local_calendar = local_calendar.lock().unwrap();
-        local_calendar.to_owned()
+                continue;
+            }
+            match tasks.iter_mut().find(|task| task.uid() == item.uid()) {
+                Some(task) => {
+                    *task = task.with_url(item.url().clone());
+                    if self.state.base().get(&task.id) == Some(task) {
+                        continue;
+                    }
+                    println!("Updating task {}", task.id);
+                    let mut updated = self.to_item(task);
+                    updated.set_sync_status(locally_modified(item.sync_status()));
+                    calendar
+                        .lock()?
+                        .update_item_sync(updated)
+                        .map_err(|error| Error::Provider(error.to_string()))?;
+                }
+                None => {
+                    calendar
+                        .lock()?
+                        .mark_for_deletion_sync(item.url())
+                        .map_err(|error| Error::Provider(error.to_string()))?;
+                }
+            }
+        }
+
+        for task in tasks.iter_mut() {
+            if !current_items.iter().any(|item| task.uid() == item.uid()) {
+                println!("Adding task {}", task.id);
+                *task = task.with_url(kitchen_fridge::utils::random_url(&self.source_url));
+                calendar
+                    .lock()?
+                    .add_item_sync(self.to_item(task))
+                    .map_err(|error| Error::Provider(error.to_string()))?;
+            }
+        }
+        // once the changes are saved in the local calendar the journal is cut
+        // back, as replaying it over that calendar would apply them twice
+        self.provider
+            .local()
+            .save_to_folder()
+            .map_err(|error| Error::Provider(format!("could not save the cache: {}", error)))?;
+        self.journal.archive(&self.unsynced_changes())?;
+        self.journal.clear(self.next_sequence)?;
+        self.state = State::new(tasks);
+        self.events = Log::new();
+        if !self.provider.sync().await {
+            return Err(Error::Provider(
+                "sync did not complete, the changes are saved locally and sent with the next sync"
+                    .to_string(),
+            ));
+        }
+        Ok(())
     }
 
-    async fn get_local_calendar_items(&mut self) -> Vec<Item> {
-        let local_calendar = self.get_local_calendar().await;
-        local_calendar
-            .get_items()
-            .await
-            .unwrap()
+    // Rewrites every item still using the legacy CATEGORIES encoding with the
+    // X-TASKS- properties. Unsynced changes are left for the next sync.
+    pub async fn migrate(&mut self) -> Result<usize> {
+        let calendar = self.local_calendar()?;
+        let legacy_items: Vec<Item> = calendar
+            .lock()?
+            .get_items_sync()
+            .map_err(|error| Error::Provider(error.to_string()))?
+            .into_values()
+            .filter(|item| item.is_task() && item.has_legacy_tags())
+            .cloned()
+            .collect();
+        let mut migrated = 0;
+        for item in legacy_items {
+            let task = match self.state.base().get(&Task::id_for_uid(item.uid())) {
+                Some(task) => task.with_url(item.url().clone()),
+                None => continue,
+            };
+            println!("Migrating task {}", task.id);
+            let mut updated = self.to_item(&task);
+            updated.set_sync_status(locally_modified(item.sync_status()));
+            calendar
+                .lock()?
+                .update_item_sync(updated)
+                .map_err(|error| Error::Provider(error.to_string()))?;
+            migrated += 1;
+        }
+        if migrated > 0 && !self.provider.sync().await {
+            return Err(Error::Provider(
+                "sync did not complete, run migrate again".to_string(),
+            ));
+        }
+        Ok(migrated)
+    }
+
+    pub fn history(&self, task_id: Uuid) -> Result<Vec<TaskChange>> {
+        let mut history = self.journal.read_history()?;
+        history.extend(self.unsynced_changes());
+        Ok(history
             .into_iter()
-            .map(|(_, item)| item.clone())
-            .collect()
+            .filter(|change| change.task_id == task_id)
+            .collect())
+    }
+
+    fn unsynced_changes(&self) -> Vec<TaskChange> {
+        let mut tasks = self.state.base().clone();
+        let mut changes = Vec::new();
+        for entry in self.events.prev.iter() {
+            // the tasks the event changed, as they were; an event that no
+            // longer applies changes none
+            let before = App::update(&mut tasks, &entry.message).unwrap_or_default();
+            // cascades change tasks beyond the ones the message names
+            let mut task_ids = entry.message.task_ids();
+            let mut cascaded: Vec<Uuid> = before
+                .iter()
+                .filter(|(task_id, task)| {
+                    !task_ids.contains(task_id) && task.as_ref() != tasks.get(task_id)
+                })
+                .map(|(task_id, _)| *task_id)
+                .collect();
+            cascaded.sort();
+            task_ids.extend(cascaded);
+            for task_id in task_ids {
+                let before = match before.get(&task_id) {
+                    Some(task) => task.as_ref(),
+                    None => tasks.get(&task_id),
+                };
+                changes.push(TaskChange {
+                    task_id,
+                    entry: entry.clone(),
+                    changes: FieldChange::between(
+                        before.map(|task| task.fields()),
+                        tasks.get(&task_id).map(|task| task.fields()),
+                    ),
+                });
+            }
+        }
+        changes
+    }
+
+    fn to_item(&self, task: &Task) -> Item {
+        let uid_of = |task_id: Uuid| match self.state.current.get(&task_id) {
+            Some(task) => task.uid(),
+            None => task_id.to_string(),
+        };
+        task.with_unmapped_tags(&self.tag_mapping)
+            .to_item(&self.source_url, &uid_of)
+    }
+
+    fn local_calendar(&self) -> Result<Arc<Mutex<CachedCalendar>>> {
+        self.provider
+            .local()
+            .get_calendar_sync(&self.source_url)
+            .ok_or_else(|| Error::CalendarNotFound(self.source_url.clone()))
+    }
+}
+
+// The subtasks of every task, indexed once so that walking the tree does not
+// search all the tasks at each level. Siblings are in order of creation.
+pub struct Tree<'a> {
+    roots: Vec<&'a Task>,
+    children: HashMap<Uuid, Vec<&'a Task>>,
+}
+
+impl<'a> Tree<'a> {
+    fn new(tasks: &'a HashMap<Uuid, Task>) -> Self {
+        let mut sorted: Vec<&Task> = tasks.values().collect();
+        sorted.sort_by(|a, b| {
+            a.creation_date()
+                .cmp(&b.creation_date())
+                .then(a.id.cmp(&b.id))
+        });
+        let mut tree = Self {
+            roots: Vec::new(),
+            children: HashMap::new(),
+        };
+        for task in sorted {
+            match task.parent_task() {
+                // tasks whose parent is missing are roots too
+                Some(parent_id) if tasks.contains_key(&parent_id) => {
+                    tree.children.entry(parent_id).or_default().push(task)
+                }
+                _ => tree.roots.push(task),
+            }
+        }
+        tree
+    }
+
+    // tasks without a parent, or whose parent is missing
+    pub fn roots(&self) -> &[&'a Task] {
+        &self.roots
+    }
+
+    pub fn children(&self, task_id: Uuid) -> &[&'a Task] {
+        self.children.get(&task_id).map_or(&[], Vec::as_slice)
+    }
+
+    // every task below task_id, each followed by its own descendants
+    pub fn descendants(&self, task_id: Uuid) -> Vec<&'a Task> {
+        let mut descendants = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack: Vec<&Task> = self.children(task_id).iter().rev().copied().collect();
+        while let Some(task) = stack.pop() {
+            // a cycle from another client would otherwise never end
+            if task.id == task_id || !seen.insert(task.id) {
+                continue;
+            }
+            stack.extend(self.children(task.id).iter().rev());
+            descendants.push(task);
+        }
+        descendants
+    }
+}
+
+const SNAPSHOT_INTERVAL: usize = 64;
+
+// The tasks an update works on, keeping each task it changes as it was
+// before, so that a failed update can be undone without copying every task
+struct ChangeSet<'a> {
+    tasks: &'a mut HashMap<Uuid, Task>,
+    before: HashMap<Uuid, Option<Task>>,
+}
+
+impl<'a> ChangeSet<'a> {
+    fn new(tasks: &'a mut HashMap<Uuid, Task>) -> Self {
+        Self {
+            tasks,
+            before: HashMap::new(),
+        }
+    }
+
+    fn keep(&mut self, task_id: Uuid) {
+        let tasks = &self.tasks;
+        self.before
+            .entry(task_id)
+            .or_insert_with(|| tasks.get(&task_id).cloned());
+    }
+
+    fn get_mut(&mut self, task_id: &Uuid) -> Option<&mut Task> {
+        self.keep(*task_id);
+        self.tasks.get_mut(task_id)
+    }
+
+    fn insert(&mut self, task_id: Uuid, task: Task) {
+        self.keep(task_id);
+        self.tasks.insert(task_id, task);
+    }
+
+    fn remove(&mut self, task_id: &Uuid) {
+        self.keep(*task_id);
+        self.tasks.remove(task_id);
+    }
+
+    fn restore(self) {
+        for (task_id, task) in self.before {
+            match task {
+                Some(task) => self.tasks.insert(task_id, task),
+                None => self.tasks.remove(&task_id),
+            };
+        }
+    }
+}
+
+impl Deref for ChangeSet<'_> {
+    type Target = HashMap<Uuid, Task>;
+
+    fn deref(&self) -> &Self::Target {
+        self.tasks
+    }
+}
+
+// The present state, kept up to date as events are recorded. Snapshots taken
+// every SNAPSHOT_INTERVAL events bound how much of the log an undo replays.
+struct State {
+    current: HashMap<Uuid, Task>,
+    snapshots: Vec<(usize, HashMap<Uuid, Task>)>,
+}
+
+impl State {
+    fn new(tasks: Vec<Task>) -> Self {
+        let current: HashMap<Uuid, Task> = tasks.into_iter().map(|task| (task.id, task)).collect();
+        Self {
+            snapshots: vec![(0, current.clone())],
+            current,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        event: &Message,
+        position: usize,
+    ) -> std::result::Result<(), ValidationError> {
+        App::update(&mut self.current, event)?;
+        let (last_snapshot, _) = self
+            .snapshots
+            .last()
+            .expect("the initial snapshot is never removed");
+        if position - last_snapshot >= SNAPSHOT_INTERVAL {
+            self.snapshots.push((position, self.current.clone()));
+        }
+        Ok(())
+    }
+
+    fn base(&self) -> &HashMap<Uuid, Task> {
+        &self.snapshots[0].1
+    }
+
+    fn rewind(&mut self, events: &[LogEntry]) {
+        let position = events.len();
+        self.snapshots
+            .retain(|(snapshot_position, _)| *snapshot_position <= position);
+        let (snapshot_position, snapshot) = self
+            .snapshots
+            .last()
+            .expect("the initial snapshot is never removed");
+        let mut current = snapshot.clone();
+        for entry in &events[*snapshot_position..] {
+            let _ = App::update(&mut current, &entry.message);
+        }
+        self.current = current;
     }
 }
 
 #[derive(Clone)]
 struct Log {
-    prev: Vec<Message>,
-    next: Vec<Message>,
+    prev: Vec<LogEntry>,
+    next: Vec<LogEntry>,
 }
 
 impl Log {
@@ -247,45 +971,940 @@ impl Log {
             self.next.insert(0, new);
         }
     }
-    pub fn add(&mut self, item: Message) {
+
+    fn last(&self) -> Option<&LogEntry> {
+        self.prev.last()
+    }
+
+    fn upcoming(&self) -> Option<&LogEntry> {
+        self.next.first()
+    }
+
+    pub fn add(&mut self, item: LogEntry) {
         self.next.clear();
         self.prev.push(item);
     }
 
     pub fn new() -> Self {
         Self {
-            prev: Vec::<Message>::new(),
-            next: Vec::<Message>::new(),
+            prev: Vec::<LogEntry>::new(),
+            next: Vec::<LogEntry>::new(),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub device: String,
+    pub message: Message,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    NoSuchTask(Uuid),
+    DuplicateTask(Uuid),
+    NoSuchParent(Uuid),
+    ParentCycle(Uuid, Uuid),
+    HasSubtasks(Uuid),
+    TimerRunning(Uuid),
+    TimerNotRunning(Uuid),
+    DependencyCycle(Uuid, Uuid),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::NoSuchTask(task_id) => write!(f, "no such task {}", task_id),
+            ValidationError::DuplicateTask(task_id) => {
+                write!(f, "a task with ID {} already exists", task_id)
+            }
+            ValidationError::NoSuchParent(parent_id) => {
+                write!(f, "no such parent task {}", parent_id)
+            }
+            ValidationError::ParentCycle(task_id, parent_id) => write!(
+                f,
+                "making {} the parent of {} would create a parent cycle",
+                parent_id, task_id
+            ),
+            ValidationError::HasSubtasks(task_id) => {
+                write!(f, "task {} has subtasks", task_id)
+            }
+            ValidationError::DependencyCycle(task_id, dependency_id) => write!(
+                f,
+                "making {} depend on {} would create a dependency cycle",
+                task_id, dependency_id
+            ),
+            ValidationError::TimerRunning(task_id) => {
+                write!(f, "the timer of task {} is already running", task_id)
+            }
+            ValidationError::TimerNotRunning(task_id) => {
+                write!(f, "the timer of task {} is not running", task_id)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskChange {
+    pub task_id: Uuid,
+    pub entry: LogEntry,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl FieldChange {
+    fn between(
+        before: Option<Vec<(&'static str, String)>>,
+        after: Option<Vec<(&'static str, String)>>,
+    ) -> Vec<Self> {
+        let value = |fields: &Option<Vec<(&'static str, String)>>, field: &str| {
+            fields.as_ref().and_then(|fields| {
+                fields
+                    .iter()
+                    .find(|(name, _)| *name == field)
+                    .map(|(_, value)| value.clone())
+            })
+        };
+        let names: Vec<&'static str> = before
+            .iter()
+            .chain(after.iter())
+            .flat_map(|fields| fields.iter().map(|(name, _)| *name))
+            .fold(Vec::new(), |mut names, name| {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+                names
+            });
+        names
+            .into_iter()
+            .map(|name| Self {
+                field: name.to_string(),
+                before: value(&before, name),
+                after: value(&after, name),
+            })
+            .filter(|change| change.before != change.after)
+            .collect()
+    }
+}
+
+// What happens to the subtasks of a removed task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RemovePolicy {
+    // fail if the task has subtasks
+    #[default]
+    Refuse,
+    DeleteSubtree,
+    // the subtasks move up to the removed task's parent
+    Reparent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CompletionPolicy {
+    pub complete_subtree: bool,
+    // complete the parent once its last incomplete subtask is completed
+    pub complete_parent: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     SetName(Uuid, String),
-    MarkComplete(Uuid),
+    SetDescription(Uuid, Option<String>),
+    MarkComplete(Uuid, CompletionPolicy),
     MarkIncomplete(Uuid),
-    SetStartDate(Uuid, Option<NaiveDateTime>),
-    SetDueDate(Uuid, Option<NaiveDateTime>),
+    MarkInProcess(Uuid),
+    MarkCancelled(Uuid),
+    SetPercentComplete(Uuid, Option<u8>),
+    SetPriority(Uuid, Priority),
+    SetStartDate(Uuid, Option<TaskDate>),
+    SetDueDate(Uuid, Option<TaskDate>),
+    SetRecurrence(Uuid, Option<Recurrence>),
+    // seconds
+    SetEstimate(Uuid, Option<i64>),
+    StartTimer(Uuid, DateTime<Utc>),
+    StopTimer(Uuid, DateTime<Utc>),
+    AddAlarm(Uuid, Alarm),
+    RemoveAlarm(Uuid, Alarm),
     AddContext(Uuid, String),
     RemoveContext(Uuid, String),
     AddProject(Uuid, String),
     RemoveProject(Uuid, String),
     AddArea(Uuid, String),
     RemoveArea(Uuid, String),
+    AddTag(Uuid, String),
+    RemoveTag(Uuid, String),
     SetMoneyNeeded(Uuid, bool),
     SetWeather(Uuid, Option<Weather>),
     SetTimeOfDay(Uuid, Option<TimeOfDay>),
     SetParentTask(Uuid, Option<Uuid>),
-    AddTask(Task),
-    RemoveTask(Uuid),
+    // the task, then the task it depends on
+    AddDependency(Uuid, Uuid),
+    RemoveDependency(Uuid, Uuid),
+    AddTask(Box<Task>),
+    RemoveTask(Uuid, RemovePolicy),
+    Batch(Vec<Message>),
+}
+
+impl Message {
+    pub fn task_ids(&self) -> Vec<Uuid> {
+        match self {
+            Message::SetName(task_id, _)
+            | Message::SetDescription(task_id, _)
+            | Message::MarkComplete(task_id, _)
+            | Message::MarkIncomplete(task_id)
+            | Message::MarkInProcess(task_id)
+            | Message::MarkCancelled(task_id)
+            | Message::SetPercentComplete(task_id, _)
+            | Message::SetPriority(task_id, _)
+            | Message::SetStartDate(task_id, _)
+            | Message::SetDueDate(task_id, _)
+            | Message::SetRecurrence(task_id, _)
+            | Message::SetEstimate(task_id, _)
+            | Message::StartTimer(task_id, _)
+            | Message::StopTimer(task_id, _)
+            | Message::AddAlarm(task_id, _)
+            | Message::RemoveAlarm(task_id, _)
+            | Message::AddContext(task_id, _)
+            | Message::RemoveContext(task_id, _)
+            | Message::AddProject(task_id, _)
+            | Message::RemoveProject(task_id, _)
+            | Message::AddArea(task_id, _)
+            | Message::RemoveArea(task_id, _)
+            | Message::AddTag(task_id, _)
+            | Message::RemoveTag(task_id, _)
+            | Message::SetMoneyNeeded(task_id, _)
+            | Message::SetWeather(task_id, _)
+            | Message::SetTimeOfDay(task_id, _)
+            | Message::SetParentTask(task_id, _)
+            | Message::AddDependency(task_id, _)
+            | Message::RemoveDependency(task_id, _)
+            | Message::RemoveTask(task_id, _) => vec![*task_id],
+            Message::AddTask(task) => vec![task.id],
+            Message::Batch(events) => events.iter().fold(Vec::new(), |mut task_ids, event| {
+                for task_id in event.task_ids() {
+                    if !task_ids.contains(&task_id) {
+                        task_ids.push(task_id);
+                    }
+                }
+                task_ids
+            }),
+        }
+    }
 }
 
-async fn get_tasks_from_items(items: Vec<Item>) -> Vec<task::Task> {
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = |date: &Option<TaskDate>| match date {
+            Some(date) => date.to_string(),
+            None => "none".to_string(),
+        };
+        match self {
+            Message::SetName(id, name) => write!(f, "rename {} to \"{}\"", id, name),
+            Message::SetDescription(id, Some(_)) => write!(f, "set description of {}", id),
+            Message::SetDescription(id, None) => write!(f, "clear description of {}", id),
+            Message::MarkComplete(id, policy) => {
+                write!(f, "mark {} complete", id)?;
+                if policy.complete_subtree {
+                    write!(f, " with its subtasks")?;
+                }
+                if policy.complete_parent {
+                    write!(f, ", completing finished parents")?;
+                }
+                Ok(())
+            }
+            Message::MarkIncomplete(id) => write!(f, "mark {} incomplete", id),
+            Message::MarkInProcess(id) => write!(f, "mark {} in process", id),
+            Message::MarkCancelled(id) => write!(f, "cancel {}", id),
+            Message::SetPercentComplete(id, Some(percent)) => {
+                write!(f, "set progress of {} to {}%", id, percent)
+            }
+            Message::SetPercentComplete(id, None) => write!(f, "clear progress of {}", id),
+            Message::SetPriority(id, priority) => {
+                write!(f, "set priority of {} to {}", id, priority)
+            }
+            Message::SetStartDate(id, start) => {
+                write!(f, "set start date of {} to {}", id, date(start))
+            }
+            Message::SetDueDate(id, due) => write!(f, "set due date of {} to {}", id, date(due)),
+            Message::SetRecurrence(id, Some(recurrence)) => {
+                write!(f, "make {} repeat {}", id, recurrence)
+            }
+            Message::SetRecurrence(id, None) => write!(f, "stop {} repeating", id),
+            Message::AddContext(id, context) => write!(f, "add context {} to {}", context, id),
+            Message::RemoveContext(id, context) => {
+                write!(f, "remove context {} from {}", context, id)
+            }
+            Message::AddProject(id, project) => write!(f, "add project {} to {}", project, id),
+            Message::RemoveProject(id, project) => {
+                write!(f, "remove project {} from {}", project, id)
+            }
+            Message::AddArea(id, area) => write!(f, "add area {} to {}", area, id),
+            Message::RemoveArea(id, area) => write!(f, "remove area {} from {}", area, id),
+            Message::SetEstimate(id, Some(estimate)) => {
+                write!(f, "estimate {} at {}", id, format_span(*estimate))
+            }
+            Message::SetEstimate(id, None) => write!(f, "clear estimate of {}", id),
+            Message::StartTimer(id, at) => {
+                write!(
+                    f,
+                    "start timer on {} at {}",
+                    id,
+                    at.format("%Y-%m-%d %H:%M")
+                )
+            }
+            Message::StopTimer(id, at) => {
+                write!(f, "stop timer on {} at {}", id, at.format("%Y-%m-%d %H:%M"))
+            }
+            Message::AddAlarm(id, alarm) => write!(f, "remind about {} {}", id, alarm),
+            Message::RemoveAlarm(id, alarm) => {
+                write!(f, "stop reminding about {} {}", id, alarm)
+            }
+            Message::AddTag(id, tag) => write!(f, "add tag {} to {}", tag, id),
+            Message::RemoveTag(id, tag) => write!(f, "remove tag {} from {}", tag, id),
+            Message::SetMoneyNeeded(id, money_needed) => {
+                write!(f, "set money needed of {} to {}", id, money_needed)
+            }
+            Message::SetWeather(id, weather) => {
+                write!(f, "set weather of {} to {:?}", id, weather)
+            }
+            Message::SetTimeOfDay(id, time_of_day) => {
+                write!(f, "set time of day of {} to {:?}", id, time_of_day)
+            }
+            Message::SetParentTask(id, Some(parent)) => {
+                write!(f, "set parent of {} to {}", id, parent)
+            }
+            Message::SetParentTask(id, None) => write!(f, "clear parent of {}", id),
+            Message::AddDependency(id, dependency) => {
+                write!(f, "make {} depend on {}", id, dependency)
+            }
+            Message::RemoveDependency(id, dependency) => {
+                write!(f, "stop {} depending on {}", id, dependency)
+            }
+            Message::AddTask(task) => write!(f, "add task \"{}\" ({})", task.name(), task.id),
+            Message::RemoveTask(id, RemovePolicy::Refuse) => write!(f, "remove task {}", id),
+            Message::RemoveTask(id, RemovePolicy::DeleteSubtree) => {
+                write!(f, "remove task {} and its subtasks", id)
+            }
+            Message::RemoveTask(id, RemovePolicy::Reparent) => {
+                write!(f, "remove task {}, moving its subtasks to its parent", id)
+            }
+            Message::Batch(events) => {
+                let events: Vec<String> = events.iter().map(|event| event.to_string()).collect();
+                write!(f, "{}", events.join("; "))
+            }
+        }
+    }
+}
+
+// An item on the server that could not be read as a task, such as an event or
+// an item with no UID. It is reported to the user and never modified.
+#[derive(Debug, Clone)]
+pub struct Quarantined {
+    pub url: Url,
+    pub reason: String,
+}
+
+fn locally_modified(status: &SyncStatus) -> SyncStatus {
+    match status {
+        SyncStatus::Synced(version_tag) | SyncStatus::LocallyModified(version_tag) => {
+            SyncStatus::LocallyModified(version_tag.clone())
+        }
+        status => status.clone(),
+    }
+}
+
+fn get_tasks_from_items(items: Vec<Item>) -> (Vec<task::Task>, Vec<Quarantined>) {
     let mut tasks: Vec<task::Task> = Vec::new();
+    let mut quarantined = Vec::new();
     for item in items {
-        let task = task::Task::from_item(item);
-        tasks.push(task);
+        let url = item.url().clone();
+        match task::Task::from_item(item) {
+            Ok(task) if tasks.iter().any(|existing| existing.id == task.id) => {
+                quarantined.push(Quarantined {
+                    url,
+                    reason: format!("another item has the UID {}", task.uid()),
+                })
+            }
+            Ok(task) => tasks.push(task),
+            Err(error) => quarantined.push(Quarantined {
+                url,
+                reason: error.to_string(),
+            }),
+        }
+    }
+    (tasks, quarantined)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::task::tests::LEGACY_TODO;
+    use kitchen_fridge::calendar::SupportedComponents;
+    use kitchen_fridge::client::Client;
+    use kitchen_fridge::ical::parse;
+    use kitchen_fridge::traits::CalDavSource;
+    use kitchen_fridge::Cache;
+    use std::path::PathBuf;
+
+    // An app over a calendar cached in a folder of its own, which holds its
+    // journal too. It only goes to the server when it syncs.
+    pub(crate) async fn test_app(name: &str, tasks: &[Task]) -> (App, PathBuf) {
+        let folder = std::env::temp_dir().join(format!("tasks-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        let source_url: Url = "https://example.com/calendars/tasks/".parse().unwrap();
+        let mut cache = Cache::new(&folder);
+        let calendar = cache
+            .create_calendar(
+                source_url.clone(),
+                "Tasks".to_string(),
+                SupportedComponents::TODO,
+                None,
+            )
+            .await
+            .unwrap();
+        for task in tasks {
+            calendar
+                .lock()
+                .unwrap()
+                .add_item_sync(task.to_item(&source_url, &|task_id| task_id.to_string()))
+                .unwrap();
+        }
+        // nothing listens there, so a sync fails straight away
+        let client = Client::new("http://127.0.0.1:9/", "user", "password").unwrap();
+        let mut app = App {
+            provider: CalDavProvider::new(client, cache),
+            source_url,
+            zone: Arc::new(Zone::utc()),
+            device: "test".to_string(),
+            tag_mapping: TagMapping::default(),
+            next_sequence: 1,
+            quarantined: Vec::new(),
+            state: State::new(Vec::new()),
+            events: Log::new(),
+            journal: Journal::new(&folder),
+        };
+        app.refresh().unwrap();
+        (app, folder)
+    }
+
+    fn tasks(names: &[&str]) -> (HashMap<Uuid, Task>, Vec<Uuid>) {
+        let tasks: Vec<Task> = names
+            .iter()
+            .map(|name| Task::new(name.to_string()))
+            .collect();
+        let ids = tasks.iter().map(|task| task.id).collect();
+        (tasks.into_iter().map(|task| (task.id, task)).collect(), ids)
+    }
+
+    #[test]
+    fn update_gives_the_changed_tasks_as_they_were() {
+        let (mut tasks, ids) = tasks(&["a", "b", "c"]);
+        let original = tasks.clone();
+        let added = Task::new("d".to_string());
+        let before = App::update(
+            &mut tasks,
+            &Message::Batch(vec![
+                Message::SetName(ids[0], "renamed".to_string()),
+                Message::AddTask(Box::new(added.clone())),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(before.len(), 2);
+        assert_eq!(before[&ids[0]], original.get(&ids[0]).cloned());
+        assert_eq!(before[&added.id], None);
+        assert_eq!(tasks[&ids[0]].name(), "renamed");
+    }
+
+    #[test]
+    fn failed_batch_leaves_the_tasks_unchanged() {
+        let (mut tasks, ids) = tasks(&["a", "b"]);
+        let original = tasks.clone();
+        let result = App::update(
+            &mut tasks,
+            &Message::Batch(vec![
+                Message::SetName(ids[0], "renamed".to_string()),
+                Message::RemoveTask(ids[1], RemovePolicy::Refuse),
+                Message::SetName(ids[1], "gone".to_string()),
+            ]),
+        );
+        assert!(matches!(result, Err(ValidationError::NoSuchTask(id)) if id == ids[1]));
+        assert_eq!(tasks, original);
+    }
+
+    // Sets each task's parent, given as (task, parent) indices into ids
+    fn set_parents(tasks: &mut HashMap<Uuid, Task>, ids: &[Uuid], parents: &[(usize, usize)]) {
+        let events = parents
+            .iter()
+            .map(|(task, parent)| Message::SetParentTask(ids[*task], Some(ids[*parent])))
+            .collect();
+        App::update(tasks, &Message::Batch(events)).unwrap();
+    }
+
+    fn complete(policy: CompletionPolicy) -> impl Fn(Uuid) -> Message {
+        move |task_id| Message::MarkComplete(task_id, policy)
+    }
+    #[test]
+    fn rejects_events_on_missing_tasks() {
+        let (mut tasks, _) = tasks(&["a"]);
+        let missing = Task::new("missing".to_string()).id;
+        for event in [
+            Message::SetName(missing, "renamed".to_string()),
+            Message::RemoveTask(missing, RemovePolicy::Refuse),
+        ] {
+            let result = App::update(&mut tasks, &event);
+            assert!(matches!(result, Err(ValidationError::NoSuchTask(id)) if id == missing));
+        }
+    }
+
+    #[test]
+    fn rejects_adding_a_task_twice() {
+        let (mut tasks, ids) = tasks(&["a"]);
+        let task = tasks[&ids[0]].clone();
+        let result = App::update(&mut tasks, &Message::AddTask(Box::new(task)));
+        assert!(matches!(result, Err(ValidationError::DuplicateTask(id)) if id == ids[0]));
+    }
+
+    #[test]
+    fn rejects_missing_parents() {
+        let (mut tasks, ids) = tasks(&["a"]);
+        let missing = Task::new("missing".to_string()).id;
+        let result = App::update(&mut tasks, &Message::SetParentTask(ids[0], Some(missing)));
+        assert!(matches!(result, Err(ValidationError::NoSuchParent(id)) if id == missing));
+        let orphan = Task::new("orphan".to_string()).set_parent_task(Some(missing));
+        let result = App::update(&mut tasks, &Message::AddTask(Box::new(orphan)));
+        assert!(matches!(result, Err(ValidationError::NoSuchParent(id)) if id == missing));
+        assert_eq!(tasks.len(), 1);
+    }
+
+    #[test]
+    fn rejects_parent_cycles() {
+        let (mut tasks, ids) = tasks(&["a", "b", "c"]);
+        let (a, b, c) = (ids[0], ids[1], ids[2]);
+        App::update(
+            &mut tasks,
+            &Message::Batch(vec![
+                Message::SetParentTask(b, Some(a)),
+                Message::SetParentTask(c, Some(b)),
+            ]),
+        )
+        .unwrap();
+        let result = App::update(&mut tasks, &Message::SetParentTask(a, Some(c)));
+        assert!(matches!(result, Err(ValidationError::ParentCycle(x, y)) if x == a && y == c));
+        let result = App::update(&mut tasks, &Message::SetParentTask(a, Some(a)));
+        assert!(matches!(result, Err(ValidationError::ParentCycle(x, y)) if x == a && y == a));
+        assert_eq!(tasks[&a].parent_task(), None);
+        assert!(App::check_parent(&tasks, c, a).is_ok());
+    }
+
+    #[test]
+    fn check_parent_ends_at_a_cycle_from_another_client() {
+        let (mut tasks, ids) = tasks(&["a", "b", "c"]);
+        let (a, b, c) = (ids[0], ids[1], ids[2]);
+        tasks.insert(a, tasks[&a].set_parent_task(Some(b)));
+        tasks.insert(b, tasks[&b].set_parent_task(Some(a)));
+        assert!(App::check_parent(&tasks, c, a).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejected_events_are_not_journaled() {
+        let (mut app, folder) = test_app("rejected", &[Task::new("a".to_string())]).await;
+        let missing = Task::new("missing".to_string()).id;
+        assert!(app
+            .new_event(Message::SetName(missing, "renamed".to_string()))
+            .is_err());
+        assert!(app.journal.read().unwrap().is_empty());
+        assert!(!app.can_undo());
+        drop(app);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_remove_a_task_with_subtasks() {
+        let (mut tasks, ids) = tasks(&["parent", "child"]);
+        set_parents(&mut tasks, &ids, &[(1, 0)]);
+        let result = App::update(
+            &mut tasks,
+            &Message::RemoveTask(ids[0], RemovePolicy::Refuse),
+        );
+        assert!(matches!(result, Err(ValidationError::HasSubtasks(id)) if id == ids[0]));
+        assert_eq!(tasks.len(), 2);
+        App::update(
+            &mut tasks,
+            &Message::RemoveTask(ids[1], RemovePolicy::Refuse),
+        )
+        .unwrap();
+        assert_eq!(tasks.len(), 1);
+    }
+
+    #[test]
+    fn removes_a_whole_subtree() {
+        let (mut tasks, ids) = tasks(&["parent", "child", "grandchild", "other"]);
+        set_parents(&mut tasks, &ids, &[(1, 0), (2, 1)]);
+        let before = App::update(
+            &mut tasks,
+            &Message::RemoveTask(ids[0], RemovePolicy::DeleteSubtree),
+        )
+        .unwrap();
+        assert_eq!(tasks.keys().collect::<Vec<_>>(), vec![&ids[3]]);
+        assert_eq!(before.len(), 3);
+    }
+
+    #[test]
+    fn moves_subtasks_up_to_the_removed_task_parent() {
+        let (mut tasks, ids) = tasks(&["root", "parent", "child", "other child"]);
+        set_parents(&mut tasks, &ids, &[(1, 0), (2, 1), (3, 1)]);
+        App::update(
+            &mut tasks,
+            &Message::RemoveTask(ids[1], RemovePolicy::Reparent),
+        )
+        .unwrap();
+        assert!(!tasks.contains_key(&ids[1]));
+        assert_eq!(tasks[&ids[2]].parent_task(), Some(ids[0]));
+        assert_eq!(tasks[&ids[3]].parent_task(), Some(ids[0]));
+        // a root task's subtasks become roots
+        App::update(
+            &mut tasks,
+            &Message::RemoveTask(ids[0], RemovePolicy::Reparent),
+        )
+        .unwrap();
+        assert_eq!(tasks[&ids[2]].parent_task(), None);
+    }
+
+    #[test]
+    fn completes_a_whole_subtree() {
+        let (mut tasks, ids) = tasks(&["parent", "child", "grandchild", "other"]);
+        set_parents(&mut tasks, &ids, &[(1, 0), (2, 1)]);
+        let complete = complete(CompletionPolicy {
+            complete_subtree: true,
+            complete_parent: false,
+        });
+        App::update(&mut tasks, &complete(ids[0])).unwrap();
+        assert!(ids[..3].iter().all(|id| tasks[id].is_closed()));
+        assert!(!tasks[&ids[3]].is_closed());
+    }
+
+    #[test]
+    fn completing_the_last_subtask_completes_each_finished_parent() {
+        let (mut tasks, ids) = tasks(&["root", "parent", "child", "done", "open"]);
+        set_parents(&mut tasks, &ids, &[(1, 0), (2, 1), (3, 0), (4, 0)]);
+        let complete = complete(CompletionPolicy {
+            complete_subtree: false,
+            complete_parent: true,
+        });
+        App::update(&mut tasks, &complete(ids[3])).unwrap();
+        App::update(&mut tasks, &complete(ids[2])).unwrap();
+        assert!(tasks[&ids[1]].is_closed());
+        // the root still has an open subtask
+        assert!(!tasks[&ids[0]].is_closed());
+        App::update(&mut tasks, &complete(ids[4])).unwrap();
+        assert!(tasks[&ids[0]].is_closed());
+    }
+
+    #[test]
+    fn completes_parents_more_than_one_level_up() {
+        let (mut tasks, ids) = tasks(&["root", "parent", "child"]);
+        set_parents(&mut tasks, &ids, &[(1, 0), (2, 1)]);
+        let complete = complete(CompletionPolicy {
+            complete_subtree: false,
+            complete_parent: true,
+        });
+        App::update(&mut tasks, &complete(ids[2])).unwrap();
+        assert!(ids.iter().all(|id| tasks[id].is_closed()));
+    }
+
+    #[test]
+    fn rejects_dependency_cycles() {
+        let (mut tasks, ids) = tasks(&["a", "b", "c"]);
+        let (a, b, c) = (ids[0], ids[1], ids[2]);
+        let result = App::update(&mut tasks, &Message::AddDependency(a, a));
+        assert!(matches!(result, Err(ValidationError::DependencyCycle(x, y)) if x == a && y == a));
+        App::update(
+            &mut tasks,
+            &Message::Batch(vec![
+                Message::AddDependency(a, b),
+                Message::AddDependency(b, c),
+            ]),
+        )
+        .unwrap();
+        let result = App::update(&mut tasks, &Message::AddDependency(c, a));
+        assert!(matches!(result, Err(ValidationError::DependencyCycle(x, y)) if x == c && y == a));
+        assert!(tasks[&c].depends_on().is_empty());
+        let missing = Task::new("missing".to_string()).id;
+        let result = App::update(&mut tasks, &Message::AddDependency(a, missing));
+        assert!(matches!(result, Err(ValidationError::NoSuchTask(id)) if id == missing));
+        assert!(App::check_dependency(&tasks, a, c).is_ok());
+    }
+
+    #[tokio::test]
+    async fn tasks_are_blocked_until_their_dependencies_are_done() {
+        let elsewhere = Task::new("elsewhere".to_string()).id;
+        let waiting = Task::new("waiting".to_string()).add_dependency(elsewhere);
+        let (mut app, folder) = test_app(
+            "blocked",
+            &[
+                Task::new("a".to_string()),
+                Task::new("b".to_string()),
+                waiting.clone(),
+            ],
+        )
+        .await;
+        // links to tasks that are not in the calendar do not block
+        assert!(!app.is_blocked(waiting.id));
+        let ids: Vec<Uuid> = app
+            .get_present_state()
+            .iter()
+            .filter(|task| task.id != waiting.id)
+            .map(|task| task.id)
+            .collect();
+        app.new_event(Message::AddDependency(ids[0], ids[1]))
+            .unwrap();
+        assert!(app.is_blocked(ids[0]));
+        assert!(!app.is_blocked(ids[1]));
+        app.new_event(Message::MarkComplete(ids[1], CompletionPolicy::default()))
+            .unwrap();
+        assert!(!app.is_blocked(ids[0]));
+        drop(app);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn removing_a_task_drops_the_links_to_it() {
+        let (mut tasks, ids) = tasks(&["a", "b", "parent", "child"]);
+        set_parents(&mut tasks, &ids, &[(3, 2)]);
+        App::update(
+            &mut tasks,
+            &Message::Batch(vec![
+                Message::AddDependency(ids[0], ids[1]),
+                Message::AddDependency(ids[0], ids[3]),
+            ]),
+        )
+        .unwrap();
+        App::update(
+            &mut tasks,
+            &Message::RemoveTask(ids[1], RemovePolicy::Refuse),
+        )
+        .unwrap();
+        assert_eq!(tasks[&ids[0]].depends_on(), &[ids[3]]);
+        // along with the links to the rest of a removed subtree
+        App::update(
+            &mut tasks,
+            &Message::RemoveTask(ids[2], RemovePolicy::DeleteSubtree),
+        )
+        .unwrap();
+        assert!(tasks[&ids[0]].depends_on().is_empty());
+    }
+
+    // What the undo tests look at, as replaying an event sets last_modified
+    // to the time it is replayed
+    fn names<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> Vec<(Uuid, String, bool)> {
+        let mut names: Vec<(Uuid, String, bool)> = tasks
+            .into_iter()
+            .map(|task| (task.id, task.name().to_string(), task.is_closed()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn undo_and_redo_move_through_the_log() {
+        let task = Task::new("a".to_string());
+        let (mut app, folder) = test_app("undo", std::slice::from_ref(&task)).await;
+        let rename = |name: &str| Message::SetName(task.id, name.to_string());
+        assert!(!app.can_undo());
+        app.new_event(rename("b")).unwrap();
+        app.new_event(rename("c")).unwrap();
+        assert_eq!(app.undo_description(), Some(rename("c").to_string()));
+        assert!(app.undo().unwrap().is_some());
+        assert_eq!(app.get_task(task.id).unwrap().name(), "b");
+        assert_eq!(app.redo_description(), Some(rename("c").to_string()));
+        app.undo().unwrap();
+        assert_eq!(app.get_task(task.id).unwrap().name(), "a");
+        assert!(app.undo().unwrap().is_none());
+        app.redo().unwrap();
+        assert_eq!(app.get_task(task.id).unwrap().name(), "b");
+        // a new event drops the events that could be redone
+        app.new_event(rename("d")).unwrap();
+        assert!(!app.can_redo());
+        assert!(app.redo().unwrap().is_none());
+        assert_eq!(app.get_task(task.id).unwrap().name(), "d");
+        drop(app);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn undo_and_redo_are_replayed_from_the_journal() {
+        let task = Task::new("a".to_string());
+        let (mut app, folder) = test_app("undo-journal", std::slice::from_ref(&task)).await;
+        for name in ["b", "c", "d"] {
+            app.new_event(Message::SetName(task.id, name.to_string()))
+                .unwrap();
+        }
+        app.undo().unwrap();
+        app.undo().unwrap();
+        app.redo().unwrap();
+        let before = names(&app.get_present_state());
+        app.refresh().unwrap();
+        assert_eq!(names(&app.get_present_state()), before);
+        assert_eq!(app.get_task(task.id).unwrap().name(), "c");
+        assert_eq!(
+            app.redo_description(),
+            Some(Message::SetName(task.id, "d".to_string()).to_string())
+        );
+        drop(app);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn undo_and_redo_cross_snapshots() {
+        let task = Task::new("0".to_string());
+        let (mut app, folder) = test_app("undo-snapshots", std::slice::from_ref(&task)).await;
+        let last = SNAPSHOT_INTERVAL + 6;
+        for name in 1..=last {
+            app.new_event(Message::SetName(task.id, name.to_string()))
+                .unwrap();
+        }
+        for _ in 0..12 {
+            app.undo().unwrap();
+        }
+        let name = app.get_task(task.id).unwrap().name().to_string();
+        assert_eq!(name, (last - 12).to_string());
+        for _ in 0..12 {
+            app.redo().unwrap();
+        }
+        assert_eq!(app.get_task(task.id).unwrap().name(), last.to_string());
+        drop(app);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    fn entry(message: Message) -> LogEntry {
+        LogEntry {
+            sequence: 0,
+            timestamp: Utc::now(),
+            device: "test".to_string(),
+            message,
+        }
+    }
+
+    // The tasks the events leave when they are applied one by one to the base
+    fn replay(base: &HashMap<Uuid, Task>, events: &[LogEntry]) -> HashMap<Uuid, Task> {
+        let mut tasks = base.clone();
+        for event in events {
+            let _ = App::update(&mut tasks, &event.message);
+        }
+        tasks
+    }
+
+    #[test]
+    fn rewind_goes_back_to_before_the_later_events() {
+        let (tasks, ids) = tasks(&["a", "b"]);
+        let missing = Task::new("missing".to_string()).id;
+        let mut state = State::new(tasks.into_values().collect());
+        let events = [
+            entry(Message::SetName(ids[0], "renamed".to_string())),
+            // kept in the log though it no longer applies
+            entry(Message::SetName(missing, "renamed".to_string())),
+            entry(Message::MarkComplete(ids[1], CompletionPolicy::default())),
+        ];
+        for (position, event) in events.iter().enumerate() {
+            let _ = state.apply(&event.message, position + 1);
+        }
+        state.rewind(&events[..2]);
+        assert_eq!(
+            names(state.current.values()),
+            names(replay(state.base(), &events[..1]).values())
+        );
+        assert!(!state.current[&ids[1]].is_closed());
+        state.rewind(&[]);
+        assert_eq!(state.current, *state.base());
+    }
+
+    #[test]
+    fn snapshots_match_a_full_replay() {
+        let (tasks, ids) = tasks(&["a", "b"]);
+        let mut state = State::new(tasks.into_values().collect());
+        let events: Vec<LogEntry> = (1..=2 * SNAPSHOT_INTERVAL + 10)
+            .map(|i| entry(Message::SetName(ids[i % 2], i.to_string())))
+            .collect();
+        let positions = |state: &State| -> Vec<usize> {
+            state
+                .snapshots
+                .iter()
+                .map(|(position, _)| *position)
+                .collect()
+        };
+        for (position, event) in events.iter().enumerate() {
+            state.apply(&event.message, position + 1).unwrap();
+        }
+        assert_eq!(
+            positions(&state),
+            vec![0, SNAPSHOT_INTERVAL, 2 * SNAPSHOT_INTERVAL]
+        );
+        for (position, snapshot) in state.snapshots.iter() {
+            assert_eq!(
+                names(snapshot.values()),
+                names(replay(state.base(), &events[..*position]).values())
+            );
+        }
+
+        // undo past the last snapshot, which goes with the events after it
+        let undone = SNAPSHOT_INTERVAL + 5;
+        state.rewind(&events[..undone]);
+        assert_eq!(positions(&state), vec![0, SNAPSHOT_INTERVAL]);
+        assert_eq!(
+            names(state.current.values()),
+            names(replay(state.base(), &events[..undone]).values())
+        );
+
+        // and redo
+        for (position, event) in events.iter().enumerate().skip(undone) {
+            state.apply(&event.message, position + 1).unwrap();
+        }
+        assert_eq!(
+            positions(&state),
+            vec![0, SNAPSHOT_INTERVAL, 2 * SNAPSHOT_INTERVAL]
+        );
+        assert_eq!(
+            names(state.current.values()),
+            names(replay(state.base(), &events).values())
+        );
+    }
+
+    #[tokio::test]
+    async fn migrate_rewrites_legacy_items() {
+        let (mut app, folder) = test_app("migrate", &[]).await;
+        let url = app.source_url.join("legacy.ics").unwrap();
+        let item = parse(LEGACY_TODO, url.clone(), SyncStatus::NotSynced).unwrap();
+        app.local_calendar()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .add_item_sync(item)
+            .unwrap();
+        app.refresh().unwrap();
+        // the items are rewritten locally even though the server is not there
+        assert!(app.migrate().await.is_err());
+        let calendar = app.local_calendar().unwrap();
+        let item = calendar
+            .lock()
+            .unwrap()
+            .get_item_by_url_sync(&url)
+            .cloned()
+            .unwrap();
+        assert!(!item.has_legacy_tags());
+        assert_eq!(
+            item.get_attributes_from_item("X-TASKS-CONTEXT"),
+            vec!["garden"]
+        );
+        assert_eq!(app.migrate().await.unwrap(), 0);
+        drop(app);
+        std::fs::remove_dir_all(folder).unwrap();
     }
-    tasks
 }
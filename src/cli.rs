@@ -0,0 +1,1117 @@
+use crate::alarm::{Alarm, Anchor};
+use crate::app::{App, CompletionPolicy, Message, RemovePolicy, TaskChange, Tree, ValidationError};
+use crate::date::TaskDate;
+use crate::duration;
+use crate::error::{Error, Result};
+use crate::recurrence::{Frequency, Recurrence};
+use crate::remind::{self, Desktop, Notifier, Script, Stdout};
+use crate::task::{Priority, Status, Task, TimeOfDay, Weather};
+use crate::zone::Zone;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+#[derive(Parser)]
+#[command(name = "tasks", about = "Manage tasks stored in a CalDAV calendar")]
+pub struct Cli {
+    /// Path to the config file, instead of $XDG_CONFIG_HOME/tasks/config.toml
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Profile from the config file to use, instead of its default_profile
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Add a new task
+    Add {
+        name: String,
+        #[command(flatten)]
+        fields: TaskFields,
+    },
+    /// List tasks, hiding closed and blocked ones unless --all is given
+    List {
+        #[arg(long)]
+        all: bool,
+        /// Also list open tasks waiting on unfinished dependencies
+        #[arg(long)]
+        blocked: bool,
+        /// Only list tasks with at least this priority
+        #[arg(long, value_parser = parse_priority)]
+        priority: Option<Priority>,
+        #[arg(long, value_enum, default_value_t = SortKey::Created)]
+        sort: SortKey,
+    },
+    /// Show every field of a task
+    Show { id: String },
+    /// Show tasks indented under their parents, hiding closed ones unless --all is given
+    Tree {
+        /// Only show this task and its subtasks
+        id: Option<String>,
+        #[arg(long)]
+        all: bool,
+    },
+    /// Mark a task as complete
+    Done {
+        id: String,
+        /// Also complete every subtask
+        #[arg(long)]
+        subtree: bool,
+        /// Also complete parents whose subtasks are then all complete
+        #[arg(long)]
+        complete_parent: bool,
+    },
+    /// Mark a completed or cancelled task as needing action again
+    UndoDone { id: String },
+    /// Mark a task as in process, optionally recording how far along it is
+    Progress {
+        id: String,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        percent: Option<u8>,
+    },
+    /// Cancel a task, keeping it but hiding it from listings
+    Cancel { id: String },
+    /// Change the fields of an existing task
+    Edit {
+        id: String,
+        #[arg(long)]
+        name: Option<String>,
+        #[command(flatten)]
+        fields: TaskFields,
+        #[arg(long, value_name = "CONTEXT")]
+        remove_context: Vec<String>,
+        #[arg(long, value_name = "AREA")]
+        remove_area: Vec<String>,
+        #[arg(long, value_name = "PROJECT")]
+        remove_project: Vec<String>,
+        #[arg(long, value_name = "TAG")]
+        remove_tag: Vec<String>,
+        #[arg(long, value_name = "ID")]
+        remove_dependency: Vec<String>,
+        #[arg(long)]
+        clear_start: bool,
+        #[arg(long)]
+        clear_due: bool,
+        #[arg(long)]
+        clear_repeat: bool,
+        #[arg(long)]
+        clear_reminders: bool,
+        #[arg(long)]
+        clear_estimate: bool,
+        #[arg(long)]
+        clear_parent: bool,
+        #[arg(long)]
+        clear_weather: bool,
+        #[arg(long)]
+        clear_time_of_day: bool,
+    },
+    /// Edit the description of a task in $EDITOR
+    Describe { id: String },
+    /// Remove a task, refusing if it has subtasks unless told what to do with them
+    Rm {
+        id: String,
+        /// Also remove every subtask
+        #[arg(long, conflicts_with = "reparent")]
+        subtree: bool,
+        /// Move the subtasks to the removed task's parent
+        #[arg(long)]
+        reparent: bool,
+    },
+    /// Show every recorded change to a task
+    History { id: String },
+    /// Undo the most recent change
+    Undo {
+        /// Only show what would be undone
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Redo the most recently undone change
+    Redo {
+        /// Only show what would be redone
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Send unsynced changes to the server and fetch its latest state
+    Sync,
+    /// List items on the server that could not be read as tasks
+    Quarantine,
+    /// Rewrite tasks stored with the old CATEGORIES encoding
+    Migrate,
+    /// Start timing work on a task
+    Start { id: String },
+    /// Stop the timer of a task, or of every task with a running timer
+    Stop { id: Option<String> },
+    /// Sum the time spent on tasks against their estimates
+    Report {
+        #[arg(long, value_enum, default_value_t = ReportKey::Project)]
+        by: ReportKey,
+    },
+    /// Keep running, sending reminders for open tasks as their alarms go off
+    Remind {
+        /// How reminders are sent
+        #[arg(long, value_enum, default_value_t = NotifierKind::Stdout)]
+        notifier: NotifierKind,
+        /// The notification command, or the script to run
+        #[arg(long, required_if_eq("notifier", "script"))]
+        command: Option<String>,
+        /// Seconds between checks
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+        /// Seconds between syncs with the server; changes made on this
+        /// machine are picked up at every check
+        #[arg(long, default_value_t = 900)]
+        sync_interval: u64,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReportKey {
+    Project,
+    Area,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum NotifierKind {
+    Stdout,
+    /// A desktop notification command, notify-send by default
+    Desktop,
+    /// A script run with the task's details in TASK_* variables
+    Script,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SortKey {
+    Created,
+    Due,
+    Priority,
+}
+
+#[derive(Args)]
+pub struct TaskFields {
+    #[arg(short, long = "context", value_name = "CONTEXT")]
+    contexts: Vec<String>,
+    #[arg(short, long = "area", value_name = "AREA")]
+    areas: Vec<String>,
+    #[arg(short, long = "project", value_name = "PROJECT")]
+    projects: Vec<String>,
+    #[arg(short, long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+    /// Notes on the task; use `tasks describe` to write them in $EDITOR
+    #[arg(long)]
+    description: Option<String>,
+    /// One of high, medium, low, none
+    #[arg(long, value_parser = parse_priority)]
+    priority: Option<Priority>,
+    /// Start date, e.g. "2022-11-01 14:00", "2022-11-01 03:00Z" or "2022-11-01" for all day
+    #[arg(long, value_parser = parse_date)]
+    start: Option<TaskDate>,
+    /// Due date, e.g. "2022-11-12 18:00", "2022-11-12 07:00Z" or "2022-11-12" for all day
+    #[arg(long, value_parser = parse_date)]
+    due: Option<TaskDate>,
+    /// One of daily, weekly, monthly, yearly, or an RRULE such as "FREQ=WEEKLY;BYDAY=MO,TH"
+    #[arg(long, value_parser = parse_recurrence)]
+    repeat: Option<Recurrence>,
+    /// Schedule the next instance from when the last one was completed
+    #[arg(long, requires = "repeat")]
+    from_completion: bool,
+    /// Remind before or after a date, e.g. "due-15m" or "start+1h", or at a time such as "2022-11-01 09:00"
+    #[arg(long, value_parser = parse_reminder)]
+    remind: Vec<ReminderSpec>,
+    /// Estimated time, e.g. "2h30m" or "1d"
+    #[arg(long, value_parser = parse_estimate)]
+    estimate: Option<i64>,
+    /// ID (or unique ID prefix) of the parent task
+    #[arg(long)]
+    parent: Option<String>,
+    /// ID (or unique ID prefix) of a task that must be finished first
+    #[arg(long, value_name = "ID")]
+    depends_on: Vec<String>,
+    /// One of sunny, cloudy, rainy, windy
+    #[arg(long, value_parser = parse_weather)]
+    weather: Option<Weather>,
+    /// One of morning, midday, afternoon, evening
+    #[arg(long, value_parser = parse_time_of_day)]
+    time_of_day: Option<TimeOfDay>,
+    #[arg(long)]
+    money_needed: Option<bool>,
+}
+
+impl TaskFields {
+    fn to_messages(&self, task_id: Uuid, tasks: &[Task], zone: &Zone) -> Result<Vec<Message>> {
+        let mut messages = Vec::new();
+        for context in self.contexts.iter() {
+            messages.push(Message::AddContext(task_id, context.clone()));
+        }
+        for area in self.areas.iter() {
+            messages.push(Message::AddArea(task_id, area.clone()));
+        }
+        for project in self.projects.iter() {
+            messages.push(Message::AddProject(task_id, project.clone()));
+        }
+        for tag in self.tags.iter() {
+            messages.push(Message::AddTag(task_id, tag.clone()));
+        }
+        if let Some(description) = &self.description {
+            messages.push(Message::SetDescription(
+                task_id,
+                Some(description.clone()).filter(|description| !description.is_empty()),
+            ));
+        }
+        if let Some(priority) = self.priority {
+            messages.push(Message::SetPriority(task_id, priority));
+        }
+        if let Some(start) = &self.start {
+            messages.push(Message::SetStartDate(
+                task_id,
+                Some(start.clone().in_zone(zone)),
+            ));
+        }
+        if let Some(due) = &self.due {
+            messages.push(Message::SetDueDate(
+                task_id,
+                Some(due.clone().in_zone(zone)),
+            ));
+        }
+        if let Some(recurrence) = &self.repeat {
+            messages.push(Message::SetRecurrence(
+                task_id,
+                Some(Recurrence {
+                    from_completion: self.from_completion,
+                    ..recurrence.clone()
+                }),
+            ));
+        }
+        if let Some(estimate) = self.estimate {
+            messages.push(Message::SetEstimate(task_id, Some(estimate)));
+        }
+        for reminder in self.remind.iter() {
+            let alarm = match reminder {
+                ReminderSpec::Relative(anchor, offset) => Alarm::Relative(*anchor, *offset),
+                ReminderSpec::At(date) => Alarm::Absolute(date.to_utc(zone)),
+            };
+            messages.push(Message::AddAlarm(task_id, alarm));
+        }
+        if let Some(parent) = &self.parent {
+            let parent_id = resolve_id(tasks, parent)?;
+            messages.push(Message::SetParentTask(task_id, Some(parent_id)));
+        }
+        for dependency in self.depends_on.iter() {
+            let dependency_id = resolve_id(tasks, dependency)?;
+            messages.push(Message::AddDependency(task_id, dependency_id));
+        }
+        if let Some(weather) = &self.weather {
+            messages.push(Message::SetWeather(task_id, Some(weather.clone())));
+        }
+        if let Some(time_of_day) = &self.time_of_day {
+            messages.push(Message::SetTimeOfDay(task_id, Some(time_of_day.clone())));
+        }
+        if let Some(money_needed) = self.money_needed {
+            messages.push(Message::SetMoneyNeeded(task_id, money_needed));
+        }
+        Ok(messages)
+    }
+}
+
+pub async fn run(command: Command, app: &mut App) -> Result<()> {
+    let tasks = app.get_present_state();
+    match command {
+        Command::Add { name, fields } => {
+            let new_task = Task::new(name);
+            let task_id = new_task.id;
+            let mut messages = vec![Message::AddTask(Box::new(new_task))];
+            messages.extend(fields.to_messages(task_id, &tasks, &app.zone)?);
+            app.new_event(batch(messages))?;
+            println!("Added task {}", task_id);
+        }
+        Command::List {
+            all,
+            blocked,
+            priority,
+            sort,
+        } => {
+            let mut tasks: Vec<&Task> = tasks
+                .iter()
+                .filter(|task| all || !task.is_closed())
+                .filter(|task| all || blocked || !app.is_blocked(task.id))
+                .filter(|task| match priority {
+                    Some(priority) => task.priority() <= priority,
+                    None => true,
+                })
+                .collect();
+            match sort {
+                SortKey::Created => {}
+                // tasks without a due date go last
+                SortKey::Due => tasks.sort_by_key(|task| {
+                    let due = task.due().map(|due| due.to_local(&app.zone));
+                    (due.is_none(), due)
+                }),
+                SortKey::Priority => tasks.sort_by_key(|task| task.priority()),
+            }
+            tasks
+                .iter()
+                .for_each(|task| println!("{}", format_summary(task, &app.zone)));
+            if !app.quarantined().is_empty() {
+                eprintln!(
+                    "{} items could not be read, see `tasks quarantine`",
+                    app.quarantined().len()
+                );
+            }
+        }
+        Command::Show { id } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            let task = app
+                .get_task(task_id)
+                .ok_or(ValidationError::NoSuchTask(task_id))?;
+            print!("{}", format_details(task, &app.zone));
+            if !task.depends_on().is_empty() {
+                let dependencies: Vec<String> = task
+                    .depends_on()
+                    .iter()
+                    .map(|id| match app.get_task(*id) {
+                        Some(dependency) => dependency.name().to_string(),
+                        None => id.to_string(),
+                    })
+                    .collect();
+                println!("Depends on:   {}", dependencies.join(", "));
+                if app.is_blocked(task_id) {
+                    println!("Blocked:      yes");
+                }
+            }
+            let ancestors: Vec<String> = app
+                .ancestors(task_id)
+                .iter()
+                .rev()
+                .map(|ancestor| ancestor.name().to_string())
+                .collect();
+            if !ancestors.is_empty() {
+                println!("Path:         {}", ancestors.join(" > "));
+            }
+            let descendants = app.tree().descendants(task_id);
+            if !descendants.is_empty() {
+                println!(
+                    "Subtasks:     {} ({} open)",
+                    descendants.len(),
+                    descendants.iter().filter(|task| !task.is_closed()).count()
+                );
+            }
+        }
+        Command::Tree { id, all } => {
+            let tree = app.tree();
+            let tops: Vec<&Task> = match id {
+                Some(id) => {
+                    let task_id = resolve_id(&tasks, &id)?;
+                    tasks.iter().filter(|task| task.id == task_id).collect()
+                }
+                None => tree.roots().to_vec(),
+            };
+            let mut seen = HashSet::new();
+            for task in tops {
+                print!("{}", format_tree(&tree, task, 0, all, &app.zone, &mut seen));
+            }
+        }
+        Command::Done {
+            id,
+            subtree,
+            complete_parent,
+        } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            let policy = CompletionPolicy {
+                complete_subtree: subtree,
+                complete_parent,
+            };
+            app.new_event(Message::MarkComplete(task_id, policy))?;
+        }
+        Command::UndoDone { id } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            app.new_event(Message::MarkIncomplete(task_id))?;
+        }
+        Command::Progress { id, percent } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            let mut messages = vec![Message::MarkInProcess(task_id)];
+            if let Some(percent) = percent {
+                messages.push(Message::SetPercentComplete(task_id, Some(percent)));
+            }
+            app.new_event(batch(messages))?;
+        }
+        Command::Cancel { id } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            app.new_event(Message::MarkCancelled(task_id))?;
+        }
+        Command::Start { id } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            app.new_event(Message::StartTimer(task_id, Utc::now()))?;
+        }
+        Command::Stop { id } => {
+            let now = Utc::now();
+            let messages: Vec<Message> = match id {
+                Some(id) => vec![Message::StopTimer(resolve_id(&tasks, &id)?, now)],
+                None => tasks
+                    .iter()
+                    .filter(|task| task.timer_started().is_some())
+                    .map(|task| Message::StopTimer(task.id, now))
+                    .collect(),
+            };
+            if messages.is_empty() {
+                return Err(Error::Input("no timer is running".to_string()));
+            }
+            app.new_event(batch(messages))?;
+        }
+        Command::Report { by } => print!("{}", format_report(&tasks, by)),
+        Command::Edit {
+            id,
+            name,
+            fields,
+            remove_context,
+            remove_area,
+            remove_project,
+            remove_tag,
+            remove_dependency,
+            clear_start,
+            clear_due,
+            clear_repeat,
+            clear_reminders,
+            clear_estimate,
+            clear_parent,
+            clear_weather,
+            clear_time_of_day,
+        } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            let mut messages = Vec::new();
+            if let Some(name) = name {
+                messages.push(Message::SetName(task_id, name));
+            }
+            messages.extend(fields.to_messages(task_id, &tasks, &app.zone)?);
+            for context in remove_context {
+                messages.push(Message::RemoveContext(task_id, context));
+            }
+            for area in remove_area {
+                messages.push(Message::RemoveArea(task_id, area));
+            }
+            for project in remove_project {
+                messages.push(Message::RemoveProject(task_id, project));
+            }
+            for tag in remove_tag {
+                messages.push(Message::RemoveTag(task_id, tag));
+            }
+            for dependency in remove_dependency {
+                let dependency_id = resolve_id(&tasks, &dependency)?;
+                messages.push(Message::RemoveDependency(task_id, dependency_id));
+            }
+            if clear_start {
+                messages.push(Message::SetStartDate(task_id, None));
+            }
+            if clear_due {
+                messages.push(Message::SetDueDate(task_id, None));
+            }
+            if clear_repeat {
+                messages.push(Message::SetRecurrence(task_id, None));
+            }
+            if clear_reminders {
+                let task = tasks.iter().find(|task| task.id == task_id);
+                for alarm in task.map(|task| task.alarms()).unwrap_or_default() {
+                    messages.push(Message::RemoveAlarm(task_id, alarm.clone()));
+                }
+            }
+            if clear_estimate {
+                messages.push(Message::SetEstimate(task_id, None));
+            }
+            if clear_parent {
+                messages.push(Message::SetParentTask(task_id, None));
+            }
+            if clear_weather {
+                messages.push(Message::SetWeather(task_id, None));
+            }
+            if clear_time_of_day {
+                messages.push(Message::SetTimeOfDay(task_id, None));
+            }
+            if messages.is_empty() {
+                return Err(Error::Input("nothing to edit".to_string()));
+            }
+            app.new_event(batch(messages))?;
+        }
+        Command::Describe { id } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            let task = app
+                .get_task(task_id)
+                .ok_or(ValidationError::NoSuchTask(task_id))?;
+            let current = task.description().unwrap_or_default().to_string();
+            let edited = edit_text(&current, &task_id.to_string())?;
+            let edited = edited.trim_end();
+            if edited == current.trim_end() {
+                println!("Description unchanged");
+            } else {
+                let description = Some(edited.to_string()).filter(|edited| !edited.is_empty());
+                app.new_event(Message::SetDescription(task_id, description))?;
+            }
+        }
+        Command::Rm {
+            id,
+            subtree,
+            reparent,
+        } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            let policy = match (subtree, reparent) {
+                (true, _) => RemovePolicy::DeleteSubtree,
+                (_, true) => RemovePolicy::Reparent,
+                _ => RemovePolicy::Refuse,
+            };
+            app.new_event(Message::RemoveTask(task_id, policy))?;
+        }
+        Command::History { id } => {
+            let task_id = resolve_id(&tasks, &id)?;
+            for change in app.history(task_id)? {
+                print!("{}", format_change(&change));
+            }
+        }
+        Command::Undo { dry_run } => {
+            if !app.can_undo() {
+                return Err(Error::Input("nothing to undo".to_string()));
+            }
+            match dry_run {
+                true => println!("Would undo: {}", app.undo_description().unwrap_or_default()),
+                false => {
+                    if let Some(entry) = app.undo()? {
+                        println!("Undid: {}", entry.message);
+                    }
+                }
+            }
+        }
+        Command::Redo { dry_run } => {
+            if !app.can_redo() {
+                return Err(Error::Input("nothing to redo".to_string()));
+            }
+            match dry_run {
+                true => println!("Would redo: {}", app.redo_description().unwrap_or_default()),
+                false => {
+                    if let Some(entry) = app.redo()? {
+                        println!("Redid: {}", entry.message);
+                    }
+                }
+            }
+        }
+        Command::Sync => app.sync().await?,
+        Command::Migrate => {
+            let migrated = app.migrate().await?;
+            println!("Migrated {} tasks", migrated);
+        }
+        Command::Remind {
+            notifier,
+            command,
+            interval,
+            sync_interval,
+        } => {
+            let notifier: Box<dyn Notifier> = match notifier {
+                NotifierKind::Stdout => Box::new(Stdout),
+                NotifierKind::Desktop => Box::new(Desktop {
+                    program: command.unwrap_or_else(|| "notify-send".to_string()),
+                }),
+                NotifierKind::Script => Box::new(Script {
+                    path: command.unwrap_or_default(),
+                }),
+            };
+            let interval = std::time::Duration::from_secs(interval.max(1));
+            let sync_interval = std::time::Duration::from_secs(sync_interval);
+            remind::watch(app, notifier.as_ref(), interval, sync_interval).await?;
+        }
+        Command::Quarantine => {
+            for quarantined in app.quarantined() {
+                println!("{}: {}", quarantined.url, quarantined.reason);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn batch(mut messages: Vec<Message>) -> Message {
+    match messages.len() {
+        1 => messages.remove(0),
+        _ => Message::Batch(messages),
+    }
+}
+
+fn resolve_id(tasks: &[Task], id: &str) -> Result<Uuid> {
+    let matches: Vec<Uuid> = tasks
+        .iter()
+        .map(|task| task.id)
+        .filter(|task_id| task_id.to_string().starts_with(&id.to_lowercase()))
+        .collect();
+    match matches.as_slice() {
+        [task_id] => Ok(*task_id),
+        [] => Err(Error::Input(format!("no task matches {}", id))),
+        _ => Err(Error::Input(format!(
+            "{} matches {} tasks",
+            id,
+            matches.len()
+        ))),
+    }
+}
+
+// Opens text in $VISUAL or $EDITOR and returns it once the editor exits
+fn edit_text(text: &str, name: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| Error::Input("EDITOR is empty".to_string()))?;
+    let path = std::env::temp_dir().join(format!("tasks-{}.txt", name));
+    let file_error = |error: std::io::Error| {
+        Error::Input(format!("could not use {}: {}", path.display(), error))
+    };
+    std::fs::write(&path, text).map_err(file_error)?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .map_err(|error| Error::Input(format!("could not run {}: {}", editor, error)));
+    let edited = match status {
+        Ok(status) if status.success() => std::fs::read_to_string(&path).map_err(file_error),
+        Ok(status) => Err(Error::Input(format!("{} exited with {}", editor, status))),
+        Err(error) => Err(error),
+    };
+    let _ = std::fs::remove_file(&path);
+    edited
+}
+
+// A trailing Z gives a time in UTC, otherwise it is in the profile's zone
+fn parse_date(value: &str) -> std::result::Result<TaskDate, String> {
+    let time = |value: &str| {
+        ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M", "%Y%m%dT%H%M%S"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    };
+    let date = match value.strip_suffix('Z') {
+        Some(utc) => time(utc).map(|time| TaskDate::Utc(DateTime::from_utc(time, Utc))),
+        None => time(value).map(TaskDate::Floating).or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(TaskDate::AllDay)
+        }),
+    };
+    date.ok_or_else(|| format!("{} is not a valid date", value))
+}
+
+#[derive(Clone)]
+enum ReminderSpec {
+    Relative(Anchor, i64),
+    At(TaskDate),
+}
+
+fn parse_reminder(value: &str) -> std::result::Result<ReminderSpec, String> {
+    let invalid = || format!("{} is not a valid reminder", value);
+    let relative = [("due", Anchor::Due), ("start", Anchor::Start)]
+        .into_iter()
+        .find_map(|(name, anchor)| Some((anchor, value.strip_prefix(name)?)));
+    match relative {
+        Some((anchor, "")) => Ok(ReminderSpec::Relative(anchor, 0)),
+        Some((anchor, offset)) => {
+            let (sign, span) = match (offset.strip_prefix('-'), offset.strip_prefix('+')) {
+                (Some(span), _) => (-1, span),
+                (_, Some(span)) => (1, span),
+                _ => return Err(invalid()),
+            };
+            let seconds = duration::parse_span(span).ok_or_else(invalid)?;
+            Ok(ReminderSpec::Relative(anchor, sign * seconds))
+        }
+        None => parse_date(value)
+            .map(ReminderSpec::At)
+            .map_err(|_| invalid()),
+    }
+}
+
+fn parse_estimate(value: &str) -> std::result::Result<i64, String> {
+    duration::parse_span(value).ok_or_else(|| format!("{} is not a valid duration", value))
+}
+
+fn parse_recurrence(value: &str) -> std::result::Result<Recurrence, String> {
+    match value.to_lowercase().as_str() {
+        "daily" => Ok(Recurrence::new(Frequency::Daily)),
+        "weekly" => Ok(Recurrence::new(Frequency::Weekly)),
+        "monthly" => Ok(Recurrence::new(Frequency::Monthly)),
+        "yearly" => Ok(Recurrence::new(Frequency::Yearly)),
+        _ => Recurrence::from_rrule(value)
+            .ok_or_else(|| format!("{} is not a supported recurrence rule", value)),
+    }
+}
+
+fn parse_priority(value: &str) -> std::result::Result<Priority, String> {
+    match value.to_lowercase().as_str() {
+        "high" => Ok(Priority::High),
+        "medium" => Ok(Priority::Medium),
+        "low" => Ok(Priority::Low),
+        "none" => Ok(Priority::None),
+        _ => Err(format!("{} is not a priority", value)),
+    }
+}
+
+fn parse_weather(value: &str) -> std::result::Result<Weather, String> {
+    match value.to_lowercase().as_str() {
+        "sunny" => Ok(Weather::Sunny),
+        "cloudy" => Ok(Weather::Cloudy),
+        "rainy" => Ok(Weather::Rainy),
+        "windy" => Ok(Weather::Windy),
+        _ => Err(format!("{} is not a kind of weather", value)),
+    }
+}
+
+fn parse_time_of_day(value: &str) -> std::result::Result<TimeOfDay, String> {
+    match value.to_lowercase().as_str() {
+        "morning" => Ok(TimeOfDay::Morning),
+        "midday" => Ok(TimeOfDay::Midday),
+        "afternoon" => Ok(TimeOfDay::Afternoon),
+        "evening" => Ok(TimeOfDay::Evening),
+        _ => Err(format!("{} is not a time of day", value)),
+    }
+}
+
+fn format_summary(task: &Task, zone: &Zone) -> String {
+    let mut summary = format!(
+        "{} [{}] {}{}",
+        &task.id.to_string()[..8],
+        match task.status() {
+            Status::NeedsAction => " ",
+            Status::InProcess => "~",
+            Status::Completed(_) => "x",
+            Status::Cancelled => "-",
+        },
+        match task.priority() {
+            Priority::High => "!!! ",
+            Priority::Medium => "!! ",
+            Priority::Low => "! ",
+            Priority::None => "",
+        },
+        task.name()
+    );
+    if let Some(due) = task.due() {
+        summary += &format!(" (due {})", due.format(zone));
+    }
+    if task.recurrence().is_some() {
+        summary += " (repeats)";
+    }
+    if task.timer_started().is_some() {
+        summary += " (timing)";
+    }
+    for context in task.contexts() {
+        summary += &format!(" @{}", context);
+    }
+    for tag in task.tags() {
+        summary += &format!(" #{}", tag);
+    }
+    summary
+}
+
+// Closed tasks are included, as their time still counts. A task in several
+// projects or areas is counted in each of them.
+fn format_report(tasks: &[Task], by: ReportKey) -> String {
+    let now = Utc::now();
+    let mut groups: Vec<(String, i64, i64)> = Vec::new();
+    for task in tasks {
+        let names = match by {
+            ReportKey::Project => task.projects(),
+            ReportKey::Area => task.areas(),
+        };
+        let names = match names.is_empty() {
+            true => vec!["(none)".to_string()],
+            false => names.to_vec(),
+        };
+        for name in names {
+            let index = match groups.iter().position(|(group, _, _)| *group == name) {
+                Some(index) => index,
+                None => {
+                    groups.push((name, 0, 0));
+                    groups.len() - 1
+                }
+            };
+            groups[index].1 += task.time_spent(now);
+            groups[index].2 += task.estimate().unwrap_or(0);
+        }
+    }
+    groups.retain(|(_, spent, estimated)| *spent > 0 || *estimated > 0);
+    groups.sort();
+    let hours = |seconds: i64| format!("{:.2}h", seconds as f64 / 3600.0);
+    let mut report = format!("{:<24} {:>10} {:>10}\n", "", "Spent", "Estimated");
+    for (name, spent, estimated) in groups.iter() {
+        report += &format!(
+            "{:<24} {:>10} {:>10}\n",
+            name,
+            hours(*spent),
+            hours(*estimated)
+        );
+    }
+    report
+}
+
+fn format_tree(
+    tree: &Tree,
+    task: &Task,
+    depth: usize,
+    all: bool,
+    zone: &Zone,
+    seen: &mut HashSet<Uuid>,
+) -> String {
+    if !seen.insert(task.id) {
+        return String::new();
+    }
+    let shown = all || !task.is_closed();
+    let mut output = match shown {
+        true => format!("{}{}\n", "    ".repeat(depth), format_summary(task, zone)),
+        false => String::new(),
+    };
+    let depth = if shown { depth + 1 } else { depth };
+    for child in tree.children(task.id) {
+        output += &format_tree(tree, child, depth, all, zone, seen);
+    }
+    output
+}
+
+fn format_details(task: &Task, zone: &Zone) -> String {
+    let mut details = format!("ID:           {}\n", task.id);
+    if task.uid() != task.id.to_string() {
+        details += &format!("UID:          {}\n", task.uid());
+    }
+    if let Some(url) = task.url() {
+        details += &format!("URL:          {}\n", url);
+    }
+    details += &format!("Name:         {}\n", task.name());
+    details += &format!(
+        "Status:       {}\n",
+        match (task.completion_date(), task.percent_complete()) {
+            (Some(date), _) => format!("completed {}", date.format("%Y-%m-%d %H:%M")),
+            (None, Some(percent)) if !task.is_closed() => {
+                format!("{} ({}%)", task.status(), percent)
+            }
+            (None, _) => task.status().to_string(),
+        }
+    );
+    if task.priority() != Priority::None {
+        details += &format!("Priority:     {}\n", task.priority());
+    }
+    if let Some(start) = task.start_date() {
+        details += &format!("Start:        {}\n", start.format(zone));
+    }
+    if let Some(due) = task.due() {
+        details += &format!("Due:          {}\n", due.format(zone));
+    }
+    if let Some(recurrence) = task.recurrence() {
+        details += &format!("Repeats:      {}\n", recurrence);
+    }
+    if let Some(estimate) = task.estimate() {
+        details += &format!("Estimate:     {}\n", duration::format_span(estimate));
+    }
+    let spent = task.time_spent(Utc::now());
+    if spent > 0 || task.timer_started().is_some() {
+        details += &format!("Time spent:   {}", duration::format_span(spent));
+        if let Some(started) = task.timer_started() {
+            details += &format!(
+                " (timer running since {})",
+                zone.to_local(started).format("%Y-%m-%d %H:%M")
+            );
+        }
+        details += "\n";
+    }
+    if !task.alarms().is_empty() {
+        let alarms: Vec<String> = task
+            .alarms()
+            .iter()
+            .map(|alarm| alarm.to_string())
+            .collect();
+        details += &format!("Reminders:    {}\n", alarms.join(", "));
+    }
+    if !task.contexts().is_empty() {
+        details += &format!("Contexts:     {}\n", task.contexts().join(", "));
+    }
+    if !task.areas().is_empty() {
+        details += &format!("Areas:        {}\n", task.areas().join(", "));
+    }
+    if !task.projects().is_empty() {
+        details += &format!("Projects:     {}\n", task.projects().join(", "));
+    }
+    if !task.tags().is_empty() {
+        details += &format!("Tags:         {}\n", task.tags().join(", "));
+    }
+    if let Some(weather) = task.weather() {
+        details += &format!("Weather:      {:?}\n", weather);
+    }
+    if let Some(time_of_day) = task.time_of_day() {
+        details += &format!("Time of day:  {:?}\n", time_of_day);
+    }
+    if task.money_needed() {
+        details += "Money needed: yes\n";
+    }
+    if let Some(parent) = task.parent_task() {
+        details += &format!("Parent:       {}\n", parent);
+    }
+    if let Some(description) = task.description() {
+        details += "Description:\n";
+        for line in description.lines() {
+            details += &format!("    {}\n", line);
+        }
+    }
+    details += &format!(
+        "Created:      {}\n",
+        task.creation_date().format("%Y-%m-%d %H:%M")
+    );
+    details += &format!(
+        "Modified:     {}\n",
+        task.last_modified().format("%Y-%m-%d %H:%M")
+    );
+    details
+}
+
+fn format_change(change: &TaskChange) -> String {
+    let mut output = format!(
+        "#{} {} on {}: {}\n",
+        change.entry.sequence,
+        change.entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        change.entry.device,
+        change.entry.message
+    );
+    for field in change.changes.iter() {
+        output += &format!(
+            "    {}: {} -> {}\n",
+            field.field,
+            field.before.as_deref().unwrap_or("(none)"),
+            field.after.as_deref().unwrap_or("(none)")
+        );
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task_in(projects: &[&str], spent: i64, estimate: Option<i64>) -> Task {
+        let started = Utc::now();
+        projects
+            .iter()
+            .fold(Task::new("task".to_string()), |task, project| {
+                task.add_project(project.to_string())
+            })
+            .start_timer(started)
+            .stop_timer(started + chrono::Duration::seconds(spent))
+            .set_estimate(estimate)
+    }
+
+    #[test]
+    fn reports_time_by_project() {
+        let tasks = [
+            task_in(&["garden"], 3600, Some(7200)),
+            task_in(&["garden", "house"], 1800, None),
+            task_in(&[], 900, None),
+            // neither spent nor estimated, so left out
+            task_in(&["shed"], 0, None),
+            task_in(&["house"], 0, Some(5400)).mark_complete(),
+        ];
+        let report = format_report(&tasks, ReportKey::Project);
+        let lines: Vec<Vec<&str>> = report
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                vec!["Spent", "Estimated"],
+                vec!["(none)", "0.25h", "0.00h"],
+                vec!["garden", "1.50h", "2.00h"],
+                vec!["house", "0.50h", "1.50h"],
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_tasks_without_an_area_together() {
+        let tasks = [task_in(&["garden"], 3600, None), task_in(&[], 3600, None)];
+        let report = format_report(&tasks, ReportKey::Area);
+        assert_eq!(report.lines().count(), 2);
+        assert!(report.lines().nth(1).unwrap().starts_with("(none)"));
+        assert!(report.contains("2.00h"));
+    }
+
+    fn naive(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(
+            parse_date("2022-11-01 09:30"),
+            Ok(TaskDate::Floating(naive("2022-11-01 09:30")))
+        );
+        assert_eq!(
+            parse_date("2022-11-01T09:30"),
+            Ok(TaskDate::Floating(naive("2022-11-01 09:30")))
+        );
+        assert_eq!(
+            parse_date("20221101T093000Z"),
+            Ok(TaskDate::Utc(DateTime::from_utc(
+                naive("2022-11-01 09:30"),
+                Utc
+            )))
+        );
+        assert_eq!(
+            parse_date("2022-11-01"),
+            Ok(TaskDate::AllDay(
+                NaiveDate::from_ymd_opt(2022, 11, 1).unwrap()
+            ))
+        );
+        assert!(parse_date("tomorrow").is_err());
+        assert!(parse_date("2022-11-01Z").is_err());
+    }
+
+    #[test]
+    fn parses_reminders() {
+        assert!(matches!(
+            parse_reminder("due-15m"),
+            Ok(ReminderSpec::Relative(Anchor::Due, -900))
+        ));
+        assert!(matches!(
+            parse_reminder("start+1h"),
+            Ok(ReminderSpec::Relative(Anchor::Start, 3600))
+        ));
+        assert!(matches!(
+            parse_reminder("due"),
+            Ok(ReminderSpec::Relative(Anchor::Due, 0))
+        ));
+        assert!(matches!(
+            parse_reminder("2022-11-01 09:00"),
+            Ok(ReminderSpec::At(TaskDate::Floating(time))) if time == naive("2022-11-01 09:00")
+        ));
+        for invalid in ["due15m", "start-soon", "later"] {
+            assert!(parse_reminder(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn resolves_unambiguous_id_prefixes() {
+        let tasks: Vec<Task> = [
+            "aaaa1111-0000-4000-8000-000000000000",
+            "aaaa2222-0000-4000-8000-000000000000",
+            "bbbb1111-0000-4000-8000-000000000000",
+        ]
+        .iter()
+        .map(|id| {
+            let mut task = Task::new("task".to_string());
+            task.id = Uuid::parse_str(id).unwrap();
+            task
+        })
+        .collect();
+        assert_eq!(resolve_id(&tasks, "AAAA1").unwrap(), tasks[0].id);
+        assert_eq!(resolve_id(&tasks, "b").unwrap(), tasks[2].id);
+        assert_eq!(
+            resolve_id(&tasks, &tasks[1].id.to_string()).unwrap(),
+            tasks[1].id
+        );
+        assert!(matches!(
+            resolve_id(&tasks, "aaaa"),
+            Err(Error::Input(message)) if message == "aaaa matches 2 tasks"
+        ));
+        assert!(matches!(resolve_id(&tasks, "c"), Err(Error::Input(_))));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::error::{Error, Result};
+use rand::Rng;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+// An example config.toml:
+//
+// default_profile = "home"
+//
+// [profiles.home]
+// server = "https://example.com/remote.php/dav/calendars/me/"
+// calendar = "https://example.com/remote.php/dav/calendars/me/tasks/"
+// timezone = "Australia/Sydney"
+// username = "me"          # falls back to the USERNAME environment variable
+// password = "secret"      # falls back to the PASSWORD environment variable
+// cache = "/tmp/tasks"     # defaults to $XDG_CACHE_HOME/tasks/<profile>
+// device = "laptop"        # defaults to an ID generated into the cache folder
+//
+// # optional, reads these categories from other clients as contexts and areas
+// [profiles.home.tags.contexts]
+// "Errands" = "errands"
+// [profiles.home.tags.areas]
+// "Work" = "work"
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    default_profile: Option<String>,
+    profiles: HashMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    pub server: String,
+    pub calendar: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub cache: Option<PathBuf>,
+    pub device: Option<String>,
+    #[serde(default)]
+    pub tags: TagMapping,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TagMapping {
+    #[serde(default)]
+    pub contexts: HashMap<String, String>,
+    #[serde(default)]
+    pub areas: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => default_config_path()?,
+        };
+        let contents = std::fs::read_to_string(&path).map_err(|error| {
+            Error::Config(format!(
+                "could not read config {}: {}",
+                path.display(),
+                error
+            ))
+        })?;
+        toml::from_str(&contents)
+            .map_err(|error| Error::Config(format!("invalid config {}: {}", path.display(), error)))
+    }
+
+    pub fn profile(&self, name: Option<&str>) -> Result<(String, Profile)> {
+        let name = match name.or(self.default_profile.as_deref()) {
+            Some(name) => name.to_string(),
+            None if self.profiles.len() == 1 => self.profiles.keys().next().unwrap().clone(),
+            None => {
+                return Err(Error::Config(
+                    "no profile given and no default_profile set".to_string(),
+                ))
+            }
+        };
+        let profile = self
+            .profiles
+            .get(&name)
+            .ok_or_else(|| Error::Config(format!("no profile named {}", name)))?;
+        Ok((name, profile.clone()))
+    }
+}
+
+impl Profile {
+    pub fn calendar_url(&self) -> Result<Url> {
+        Url::parse(&self.calendar).map_err(|error| {
+            Error::Config(format!("invalid calendar URL {}: {}", self.calendar, error))
+        })
+    }
+
+    pub fn username(&self) -> Result<String> {
+        self.username
+            .clone()
+            .or_else(|| std::env::var("USERNAME").ok())
+            .ok_or_else(|| Error::Config("no username in profile or USERNAME".to_string()))
+    }
+
+    pub fn password(&self) -> Result<String> {
+        self.password
+            .clone()
+            .or_else(|| std::env::var("PASSWORD").ok())
+            .ok_or_else(|| Error::Config("no password in profile or PASSWORD".to_string()))
+    }
+
+    pub fn cache_path(&self, profile_name: &str) -> Result<PathBuf> {
+        match &self.cache {
+            Some(cache) => Ok(cache.clone()),
+            None => Ok(xdg_dir("XDG_CACHE_HOME", ".cache")?
+                .join("tasks")
+                .join(profile_name)),
+        }
+    }
+
+    pub fn device_id(&self, cache_path: &Path) -> Result<String> {
+        if let Some(device) = &self.device {
+            return Ok(device.clone());
+        }
+        let path = cache_path.join("device_id");
+        match std::fs::read_to_string(&path) {
+            Ok(device) => Ok(device.trim().to_string()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                let device = uuid::Builder::from_random_bytes(rand::thread_rng().gen())
+                    .into_uuid()
+                    .to_string();
+                std::fs::create_dir_all(cache_path)
+                    .and_then(|_| std::fs::write(&path, &device))
+                    .map_err(|error| {
+                        Error::Config(format!("could not write {}: {}", path.display(), error))
+                    })?;
+                Ok(device)
+            }
+            Err(error) => Err(Error::Config(format!(
+                "could not read {}: {}",
+                path.display(),
+                error
+            ))),
+        }
+    }
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_config_path() -> Result<PathBuf> {
+    Ok(xdg_dir("XDG_CONFIG_HOME", ".config")?
+        .join("tasks")
+        .join("config.toml"))
+}
+
+fn xdg_dir(variable: &str, fallback: &str) -> Result<PathBuf> {
+    match std::env::var_os(variable) {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(fallback))
+            .ok_or_else(|| Error::Config(format!("neither {} nor HOME is set", variable))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    const PROFILES: &str = r#"
+[profiles.home]
+server = "https://example.com/dav/"
+calendar = "https://example.com/dav/home/"
+
+[profiles.work]
+server = "https://example.org/dav/"
+calendar = "https://example.org/dav/work/"
+cache = "/var/cache/work-tasks"
+"#;
+
+    #[test]
+    fn picks_the_profile_by_name_then_the_default() {
+        let with_default = config(&format!("default_profile = \"work\"\n{}", PROFILES));
+        let (name, profile) = with_default.profile(Some("home")).unwrap();
+        assert_eq!(name, "home");
+        assert_eq!(profile.calendar, "https://example.com/dav/home/");
+        assert_eq!(with_default.profile(None).unwrap().0, "work");
+        assert!(matches!(
+            with_default.profile(Some("elsewhere")),
+            Err(Error::Config(_))
+        ));
+        // with several profiles and no default, one has to be named
+        assert!(matches!(
+            config(PROFILES).profile(None),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn picks_the_only_profile() {
+        let only = config(
+            "[profiles.home]\nserver = \"https://example.com/dav/\"\ncalendar = \"https://example.com/dav/home/\"\n",
+        );
+        let (name, profile) = only.profile(None).unwrap();
+        assert_eq!(name, "home");
+        assert_eq!(profile.timezone, "UTC");
+    }
+
+    #[test]
+    fn keeps_each_profile_cache_apart() {
+        let config = config(PROFILES);
+        let (name, work) = config.profile(Some("work")).unwrap();
+        assert_eq!(
+            work.cache_path(&name).unwrap(),
+            PathBuf::from("/var/cache/work-tasks")
+        );
+        std::env::set_var("XDG_CACHE_HOME", "/home/me/.cache");
+        let (name, home) = config.profile(Some("home")).unwrap();
+        assert_eq!(
+            home.cache_path(&name).unwrap(),
+            PathBuf::from("/home/me/.cache/tasks/home")
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::task::property_param;
+use crate::zone::Zone;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use ical::property::Property;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// A DTSTART or DUE in whichever of the iCalendar forms it was given in, so it
+// is written back the same way. Untagged so that journals holding plain
+// NaiveDateTime values still read as floating times.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TaskDate {
+    Utc(DateTime<Utc>),
+    Floating(NaiveDateTime),
+    AllDay(NaiveDate),
+    // a local time in the named zone
+    Zoned(NaiveDateTime, String),
+}
+
+impl TaskDate {
+    pub fn from_property(property: &Property) -> Option<Self> {
+        let value = property.value.as_deref()?.trim();
+        let param = |name: &str| property_param(property, name);
+        if param("VALUE").is_some_and(|kind| kind.eq_ignore_ascii_case("DATE")) || value.len() == 8
+        {
+            return NaiveDate::parse_from_str(value, "%Y%m%d")
+                .ok()
+                .map(TaskDate::AllDay);
+        }
+        if let Some(value) = value.strip_suffix('Z') {
+            return NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+                .ok()
+                .map(|time| TaskDate::Utc(DateTime::from_utc(time, Utc)));
+        }
+        let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+        Some(match param("TZID") {
+            Some(zone) => TaskDate::Zoned(time, zone.to_string()),
+            None => TaskDate::Floating(time),
+        })
+    }
+
+    pub fn to_property(&self, name: &str) -> Property {
+        let (params, value) = match self {
+            TaskDate::Utc(time) => (None, time.format("%Y%m%dT%H%M%SZ").to_string()),
+            TaskDate::Floating(time) => (None, time.format("%Y%m%dT%H%M%S").to_string()),
+            TaskDate::AllDay(date) => (
+                Some(vec![("VALUE".to_owned(), vec!["DATE".to_owned()])]),
+                date.format("%Y%m%d").to_string(),
+            ),
+            TaskDate::Zoned(time, zone) => (
+                Some(vec![("TZID".to_owned(), vec![zone.clone()])]),
+                time.format("%Y%m%dT%H%M%S").to_string(),
+            ),
+        };
+        Property {
+            name: name.to_owned(),
+            params,
+            value: Some(value),
+        }
+    }
+
+    // A time typed in without a zone is taken to be in the profile's zone
+    pub fn in_zone(self, zone: &Zone) -> Self {
+        match self {
+            TaskDate::Floating(time) if zone.name() == "UTC" => {
+                TaskDate::Utc(DateTime::from_utc(time, Utc))
+            }
+            TaskDate::Floating(time) => TaskDate::Zoned(time, zone.name().to_string()),
+            date => date,
+        }
+    }
+
+    // the date and time as written, in the date's own zone
+    pub fn wall_time(&self) -> NaiveDateTime {
+        match self {
+            TaskDate::Utc(time) => time.naive_utc(),
+            TaskDate::Floating(time) | TaskDate::Zoned(time, _) => *time,
+            TaskDate::AllDay(date) => date.and_hms_opt(0, 0, 0).unwrap_or_default(),
+        }
+    }
+
+    // The wall time in the date's own zone at the given instant. Floating and
+    // all-day dates have no zone of their own, so UTC is used for them.
+    pub fn wall_time_at(&self, instant: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            TaskDate::Zoned(_, zone) => match Zone::load(zone) {
+                Ok(zone) => zone.to_local(instant),
+                Err(_) => instant.naive_utc(),
+            },
+            _ => instant.naive_utc(),
+        }
+    }
+
+    pub fn shifted(&self, by: Duration) -> Self {
+        match self {
+            TaskDate::Utc(time) => TaskDate::Utc(*time + by),
+            TaskDate::Floating(time) => TaskDate::Floating(*time + by),
+            TaskDate::AllDay(date) => TaskDate::AllDay(*date + Duration::days(by.num_days())),
+            TaskDate::Zoned(time, zone) => TaskDate::Zoned(*time + by, zone.clone()),
+        }
+    }
+
+    // The date as a local time in `zone`. Zones that are not known to the
+    // system are shown as written.
+    pub fn to_local(&self, zone: &Zone) -> NaiveDateTime {
+        match self {
+            TaskDate::Utc(time) => zone.to_local(*time),
+            TaskDate::Zoned(time, name) => match Zone::load(name) {
+                Ok(own_zone) => zone.to_local(own_zone.to_utc(*time)),
+                Err(_) => *time,
+            },
+            TaskDate::Floating(time) => *time,
+            TaskDate::AllDay(_) => self.wall_time(),
+        }
+    }
+
+    // Floating and all-day dates are taken to be in `zone`
+    pub fn to_utc(&self, zone: &Zone) -> DateTime<Utc> {
+        match self {
+            TaskDate::Utc(time) => *time,
+            TaskDate::Zoned(time, name) => match Zone::load(name) {
+                Ok(own_zone) => own_zone.to_utc(*time),
+                Err(_) => zone.to_utc(*time),
+            },
+            _ => zone.to_utc(self.wall_time()),
+        }
+    }
+
+    pub fn format(&self, zone: &Zone) -> String {
+        match self {
+            TaskDate::AllDay(date) => date.format("%Y-%m-%d").to_string(),
+            _ => self.to_local(zone).format("%Y-%m-%d %H:%M").to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TaskDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskDate::Utc(time) => write!(f, "{} UTC", time.format("%Y-%m-%d %H:%M")),
+            TaskDate::Floating(time) => write!(f, "{}", time.format("%Y-%m-%d %H:%M")),
+            TaskDate::AllDay(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+            TaskDate::Zoned(time, zone) => {
+                write!(f, "{} {}", time.format("%Y-%m-%d %H:%M"), zone)
+            }
+        }
+    }
+}
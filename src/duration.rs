@@ -0,0 +1,142 @@
+// An RFC 5545 duration such as "-PT15M", "P1D" or "-P1DT2H", in seconds
+pub fn parse_duration(value: &str) -> Option<i64> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(value) => (-1, value),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let value = value.strip_prefix('P')?;
+    let mut seconds = 0;
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if !in_time && number.is_empty() => in_time = true,
+            _ => {
+                let count: i64 = number.parse().ok()?;
+                number.clear();
+                seconds += count
+                    * match (c, in_time) {
+                        ('W', false) => 604800,
+                        ('D', false) => 86400,
+                        ('H', true) => 3600,
+                        ('M', true) => 60,
+                        ('S', true) => 1,
+                        _ => return None,
+                    };
+            }
+        }
+    }
+    match number.is_empty() {
+        true => Some(sign * seconds),
+        false => None,
+    }
+}
+
+pub fn format_duration(seconds: i64) -> String {
+    let sign = if seconds < 0 { "-" } else { "" };
+    let mut seconds = seconds.abs();
+    let days = seconds / 86400;
+    seconds %= 86400;
+    let mut duration = format!("{}P", sign);
+    if days > 0 {
+        duration += &format!("{}D", days);
+    }
+    if seconds > 0 || days == 0 {
+        duration += "T";
+        let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+        if hours > 0 {
+            duration += &format!("{}H", hours);
+        }
+        if minutes > 0 {
+            duration += &format!("{}M", minutes);
+        }
+        if seconds > 0 || (hours == 0 && minutes == 0) {
+            duration += &format!("{}S", seconds);
+        }
+    }
+    duration
+}
+
+// A duration the way it is typed on the command line, such as "1d2h" or "15m"
+pub fn format_span(seconds: i64) -> String {
+    let mut span = String::new();
+    let mut rest = seconds;
+    for (unit, length) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if rest >= length {
+            span += &format!("{}{}", rest / length, unit);
+            rest %= length;
+        }
+    }
+    match span.is_empty() {
+        true => "0m".to_string(),
+        false => span,
+    }
+}
+
+pub fn parse_span(value: &str) -> Option<i64> {
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            _ => {
+                let count: i64 = number.parse().ok()?;
+                number.clear();
+                seconds += count
+                    * match c {
+                        'w' => 604800,
+                        'd' => 86400,
+                        'h' => 3600,
+                        'm' => 60,
+                        's' => 1,
+                        _ => return None,
+                    };
+            }
+        }
+    }
+    match number.is_empty() && !value.is_empty() {
+        true => Some(seconds),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("-PT15M"), Some(-900));
+        assert_eq!(parse_duration("P1D"), Some(86400));
+        assert_eq!(parse_duration("-P1DT2H"), Some(-93600));
+        assert_eq!(parse_duration("+P2W"), Some(1209600));
+        assert_eq!(parse_duration("PT0S"), Some(0));
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("PT15"), None);
+        assert_eq!(parse_duration("15M"), None);
+    }
+
+    #[test]
+    fn formats_durations_that_parse_back() {
+        for seconds in [0, 59, -900, 3600, 86400, -93600, 90061] {
+            assert_eq!(parse_duration(&format_duration(seconds)), Some(seconds));
+        }
+        assert_eq!(format_duration(0), "PT0S");
+        assert_eq!(format_duration(-900), "-PT15M");
+        assert_eq!(format_duration(86400), "P1D");
+        assert_eq!(format_duration(90061), "P1DT1H1M1S");
+    }
+
+    #[test]
+    fn parses_and_formats_spans() {
+        assert_eq!(parse_span("1d2h15m"), Some(94500));
+        assert_eq!(parse_span("1w"), Some(604800));
+        assert_eq!(parse_span("90s"), Some(90));
+        assert_eq!(parse_span(""), None);
+        assert_eq!(parse_span("15"), None);
+        assert_eq!(parse_span("2y"), None);
+        assert_eq!(format_span(94500), "1d2h15m");
+        assert_eq!(format_span(0), "0m");
+    }
+}
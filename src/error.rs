@@ -0,0 +1,54 @@
+use crate::app::ValidationError;
+use std::{fmt, io};
+use url::Url;
+
+#[derive(Debug)]
+pub enum Error {
+    Config(String),
+    Provider(String),
+    CalendarNotFound(Url),
+    Lock,
+    Parse(String),
+    Validation(ValidationError),
+    Journal(io::Error),
+    Input(String),
+    Notify(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "{}", message),
+            Error::Provider(message) => write!(f, "{}", message),
+            Error::CalendarNotFound(url) => write!(f, "no calendar at {}", url),
+            Error::Lock => write!(f, "the local calendar is unusable after a failed update"),
+            Error::Parse(message) => write!(f, "{}", message),
+            Error::Validation(error) => write!(f, "{}", error),
+            Error::Journal(error) => write!(f, "could not access the journal: {}", error),
+            Error::Input(message) => write!(f, "{}", message),
+            Error::Notify(message) => write!(f, "could not send a reminder: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ValidationError> for Error {
+    fn from(error: ValidationError) -> Self {
+        Error::Validation(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Journal(error)
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for Error {
+    fn from(_: std::sync::PoisonError<T>) -> Self {
+        Error::Lock
+    }
+}
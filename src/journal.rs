@@ -0,0 +1,150 @@
+use crate::app::{LogEntry, TaskChange};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+const HISTORY_FILE: &str = "history.jsonl";
+
+// The journal holds one JSON encoded entry per line, recording every change to
+// the event log that has not yet been synced to the server. Once a sync
+// succeeds it is cut back to a checkpoint holding the next sequence number,
+// and the synced changes are moved to the history file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JournalEntry {
+    Add(Box<LogEntry>),
+    Back,
+    Forward,
+    Checkpoint(u64),
+}
+
+pub struct Journal {
+    folder: PathBuf,
+}
+
+impl Journal {
+    pub fn new(folder: &Path) -> Self {
+        Self {
+            folder: folder.to_path_buf(),
+        }
+    }
+
+    pub fn read(&self) -> io::Result<Vec<JournalEntry>> {
+        read_lines(&self.folder.join(JOURNAL_FILE))
+    }
+
+    pub fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        fs::create_dir_all(&self.folder)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.folder.join(JOURNAL_FILE))?;
+        write_line(&mut file, entry)?;
+        file.sync_data()
+    }
+
+    pub fn clear(&self, next_sequence: u64) -> io::Result<()> {
+        fs::create_dir_all(&self.folder)?;
+        let mut file = File::create(self.folder.join(JOURNAL_FILE))?;
+        write_line(&mut file, &JournalEntry::Checkpoint(next_sequence))?;
+        file.sync_data()
+    }
+
+    pub fn read_history(&self) -> io::Result<Vec<TaskChange>> {
+        read_lines(&self.folder.join(HISTORY_FILE))
+    }
+
+    pub fn archive(&self, changes: &[TaskChange]) -> io::Result<()> {
+        fs::create_dir_all(&self.folder)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.folder.join(HISTORY_FILE))?;
+        for change in changes {
+            write_line(&mut file, change)?;
+        }
+        file.sync_data()
+    }
+}
+
+// A line that cannot be read at the end of the file is what is left of a
+// write that was cut short, so it is dropped and the file truncated before it.
+// Lines that cannot be read anywhere else mean the file is corrupt.
+fn read_lines<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let lines = BufReader::new(file)
+        .split(b'\n')
+        .collect::<io::Result<Vec<Vec<u8>>>>()?;
+    let is_blank = |line: &Vec<u8>| line.iter().all(u8::is_ascii_whitespace);
+    let last = lines.iter().rposition(|line| !is_blank(line));
+    let mut values = Vec::new();
+    let mut offset = 0;
+    for (number, line) in lines.iter().enumerate() {
+        let start = offset;
+        offset += line.len() as u64 + 1;
+        if is_blank(line) {
+            continue;
+        }
+        match serde_json::from_slice(line) {
+            Ok(value) => values.push(value),
+            Err(_) if Some(number) == last => {
+                OpenOptions::new().write(true).open(path)?.set_len(start)?;
+            }
+            Err(error) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} line {}: {}", path.display(), number + 1, error),
+                ))
+            }
+        }
+    }
+    Ok(values)
+}
+
+fn write_line<T: Serialize>(file: &mut File, value: &T) -> io::Result<()> {
+    let line = serde_json::to_string(value)?;
+    writeln!(file, "{}", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal_with(name: &str, contents: &str) -> (Journal, PathBuf) {
+        let folder = std::env::temp_dir().join(format!("tasks-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join(JOURNAL_FILE), contents).unwrap();
+        (Journal::new(&folder), folder)
+    }
+
+    #[test]
+    fn drops_a_cut_short_last_line() {
+        let (journal, folder) = journal_with("cut-short", "{\"Checkpoint\":3}\n{\"Add\":{\"se");
+        let entries = journal.read().unwrap();
+        assert!(matches!(entries[..], [JournalEntry::Checkpoint(3)]));
+        journal.append(&JournalEntry::Back).unwrap();
+        let entries = journal.read().unwrap();
+        assert!(matches!(
+            entries[..],
+            [JournalEntry::Checkpoint(3), JournalEntry::Back]
+        ));
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn reports_a_bad_line_before_the_end() {
+        let (journal, folder) = journal_with("corrupt", "{\"Checkpoint\":3}\nnot json\n\"Back\"\n");
+        assert_eq!(
+            journal.read().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
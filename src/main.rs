@@ -1,65 +1,57 @@
-use chrono::NaiveDateTime;
+use clap::Parser;
 use dotenvy::dotenv;
-use kitchen_fridge::{
-    traits::{CalDavSource, DavCalendar},
-    *,
-};
+use kitchen_fridge::*;
 use std::path::Path;
+mod alarm;
 mod app;
+mod cli;
+mod config;
+mod date;
+mod duration;
+mod error;
+mod journal;
+mod recurrence;
+mod remind;
 mod task;
 mod utils;
-use url::Url;
-
-use crate::task::Weather;
+mod zone;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    let calendar_provider = get_calendar().await;
-    let calendar_url =
-        Url::parse("https://sashin.online/remote.php/dav/calendars/sashin/rust-playground/")
-            .unwrap();
-    let mut app = app::App::new(calendar_provider, calendar_url).await;
-    let new_task = task::Task::new("Helllo world".to_string());
-    app.new_event(app::Message::AddTask(new_task.clone()));
-    app.new_event(app::Message::AddContext(
-        new_task.id,
-        "Wumpa islands".to_string(),
-    ));
-    app.new_event(app::Message::AddContext(
-        new_task.id,
-        "Dragon Kingdom".to_string(),
-    ));
-    app.new_event(app::Message::AddContext(new_task.id, "Avalar".to_string()));
-    app.new_event(app::Message::AddContext(
-        new_task.id,
-        "Forgotten Realms".to_string(),
-    ));
-    app.new_event(app::Message::AddContext(
-        new_task.id,
-        "Warp Room".to_string(),
-    ));
-    app.new_event(app::Message::RemoveContext(
-        new_task.id,
-        "Forgotten Realms".to_string(),
-    ));
-    let start_date = NaiveDateTime::parse_from_str("20221101T140000", "%Y%m%dT%H%M%S").ok();
-    app.new_event(app::Message::SetStartDate(new_task.id, start_date));
-    let due_date = NaiveDateTime::parse_from_str("20221112T180000", "%Y%m%dT%H%M%S").ok();
-    app.new_event(app::Message::SetDueDate(new_task.id, due_date));
-    app.sync().await;
-    println!("\n{:#?}", app.get_present_state());
+    let cli = cli::Cli::parse();
+    if let Err(error) = run(cli).await {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
 }
 
-async fn get_calendar() -> CalDavProvider {
-    let username: String = std::env::var("USERNAME").unwrap();
-    let password: String = std::env::var("PASSWORD").unwrap();
-    let client = Client::new(
-        "https://sashin.online/remote.php/dav/calendars/sashin/tasks/",
-        username,
-        password,
+async fn run(cli: cli::Cli) -> error::Result<()> {
+    let config = config::Config::load(cli.config.as_deref())?;
+    let (profile_name, profile) = config.profile(cli.profile.as_deref())?;
+    let cache_path = profile.cache_path(&profile_name)?;
+    let calendar_provider = get_calendar(&cache_path, &profile)?;
+    let calendar_url = profile.calendar_url()?;
+    let device = profile.device_id(&cache_path)?;
+    let journal = journal::Journal::new(&cache_path);
+    let zone = zone::Zone::load(&profile.timezone)?;
+    let mut app = app::App::new(
+        calendar_provider,
+        calendar_url,
+        zone,
+        device,
+        profile.tags,
+        journal,
     )
-    .unwrap();
-    let cache = Cache::new(Path::new("tasks_data"));
-    CalDavProvider::new(client, cache)
+    .await?;
+    cli::run(cli.command, &mut app).await
+}
+
+fn get_calendar(cache_path: &Path, profile: &config::Profile) -> error::Result<CalDavProvider> {
+    let client = Client::new(&profile.server, profile.username()?, profile.password()?).map_err(
+        |error| error::Error::Config(format!("invalid server {}: {}", profile.server, error)),
+    )?;
+    // the cache holds changes that were not sent yet, see App::sync
+    let cache = Cache::from_folder(cache_path).unwrap_or_else(|_| Cache::new(cache_path));
+    Ok(CalDavProvider::new(client, cache))
 }
@@ -0,0 +1,321 @@
+use crate::date::TaskDate;
+use crate::zone::Zone;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+// The subset of an RFC 5545 RRULE that tasks are scheduled with. Rules using
+// anything else are not parsed, and stay on the item untouched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    // only used with a weekly frequency
+    pub by_day: Vec<Weekday>,
+    // occurrences left, including the current one
+    pub count: Option<u32>,
+    // a date, UTC or floating time, kept in the form it was given in
+    pub until: Option<TaskDate>,
+    // the next instance is scheduled from when the last one was completed,
+    // rather than from its own dates
+    pub from_completion: bool,
+}
+
+impl Recurrence {
+    pub fn new(frequency: Frequency) -> Self {
+        Self {
+            frequency,
+            interval: 1,
+            by_day: Vec::new(),
+            count: None,
+            until: None,
+            from_completion: false,
+        }
+    }
+
+    pub fn from_rrule(rule: &str) -> Option<Self> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+        let mut frequency = None;
+        let mut recurrence = Recurrence::new(Frequency::Daily);
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part.split_once('=')?;
+            match name.to_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return None,
+                    })
+                }
+                "INTERVAL" => recurrence.interval = value.parse().ok().filter(|i| *i > 0)?,
+                "COUNT" => recurrence.count = Some(value.parse().ok()?),
+                "UNTIL" => recurrence.until = Some(parse_until(value)?),
+                "BYDAY" => {
+                    recurrence.by_day = value
+                        .split(',')
+                        .map(parse_weekday)
+                        .collect::<Option<Vec<Weekday>>>()?
+                }
+                "WKST" if value.eq_ignore_ascii_case("MO") => {}
+                _ => return None,
+            }
+        }
+        recurrence.frequency = frequency?;
+        if !recurrence.by_day.is_empty() && recurrence.frequency != Frequency::Weekly {
+            return None;
+        }
+        Some(recurrence)
+    }
+
+    pub fn to_rrule(&self) -> String {
+        let mut rule = format!(
+            "FREQ={}",
+            match self.frequency {
+                Frequency::Daily => "DAILY",
+                Frequency::Weekly => "WEEKLY",
+                Frequency::Monthly => "MONTHLY",
+                Frequency::Yearly => "YEARLY",
+            }
+        );
+        if self.interval != 1 {
+            rule += &format!(";INTERVAL={}", self.interval);
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<&str> = self.by_day.iter().map(|day| format_weekday(*day)).collect();
+            rule += &format!(";BYDAY={}", days.join(","));
+        }
+        if let Some(count) = self.count {
+            rule += &format!(";COUNT={}", count);
+        }
+        if let Some(until) = &self.until {
+            rule += &format!(";UNTIL={}", format_until(until));
+        }
+        rule
+    }
+
+    // The first occurrence after `after`, or None once the count has run out.
+    // Whether it is past UNTIL is left to `ends_before`, which needs the zone
+    // of the task's dates.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        if self.count.is_some_and(|count| count <= 1) {
+            return None;
+        }
+        let interval = self.interval as i64;
+        let next = match self.frequency {
+            Frequency::Daily => after + Duration::days(interval),
+            Frequency::Weekly if self.by_day.is_empty() => after + Duration::weeks(interval),
+            Frequency::Weekly => {
+                let week_start = |date: NaiveDateTime| {
+                    date.date() - Duration::days(date.weekday().num_days_from_monday() as i64)
+                };
+                (1..=7 * interval)
+                    .map(|days| after + Duration::days(days))
+                    .find(|date| {
+                        self.by_day.contains(&date.weekday())
+                            && ((week_start(*date) - week_start(after)).num_weeks() % interval) == 0
+                    })?
+            }
+            // months and years without the day of the anchor are skipped
+            Frequency::Monthly => (1..=12)
+                .map(|step| add_months(after, step * self.interval))
+                .find_map(|date| date)?,
+            Frequency::Yearly => (1..=8)
+                .map(|step| add_months(after, step * self.interval * 12))
+                .find_map(|date| date)?,
+        };
+        Some(next)
+    }
+
+    // Whether the rule ends before the occurrence at `next`. A floating UNTIL
+    // is in the same wall time as the task's dates and a date-only one takes
+    // in the whole day, otherwise both are compared in UTC.
+    pub fn ends_before(&self, next: &TaskDate) -> bool {
+        match &self.until {
+            None => false,
+            Some(TaskDate::AllDay(until)) => next.wall_time().date() > *until,
+            Some(TaskDate::Floating(until)) => next.wall_time() > *until,
+            Some(until) => next.to_utc(&Zone::utc()) > until.to_utc(&Zone::utc()),
+        }
+    }
+
+    // the same rule, for the instance after this one
+    pub fn advance(&self) -> Self {
+        Self {
+            count: self.count.map(|count| count.saturating_sub(1)),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_rrule())?;
+        if self.from_completion {
+            write!(f, " from completion")?;
+        }
+        Ok(())
+    }
+}
+
+fn add_months(date: NaiveDateTime, months: u32) -> Option<NaiveDateTime> {
+    let month0 = date.month0() + months;
+    let year = date.year() + (month0 / 12) as i32;
+    NaiveDate::from_ymd_opt(year, month0 % 12 + 1, date.day()).map(|day| day.and_time(date.time()))
+}
+
+fn parse_until(value: &str) -> Option<TaskDate> {
+    let value = value.trim();
+    if let Some(value) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .ok()
+            .map(|time| TaskDate::Utc(DateTime::from_utc(time, Utc)));
+    }
+    if value.len() == 8 {
+        return NaiveDate::parse_from_str(value, "%Y%m%d")
+            .ok()
+            .map(TaskDate::AllDay);
+    }
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .ok()
+        .map(TaskDate::Floating)
+}
+
+fn format_until(until: &TaskDate) -> String {
+    match until {
+        TaskDate::Utc(time) => time.format("%Y%m%dT%H%M%SZ").to_string(),
+        TaskDate::AllDay(date) => date.format("%Y%m%d").to_string(),
+        TaskDate::Floating(time) | TaskDate::Zoned(time, _) => {
+            time.format("%Y%m%dT%H%M%S").to_string()
+        }
+    }
+}
+
+fn parse_weekday(value: &str) -> Option<Weekday> {
+    match value.trim().to_uppercase().as_str() {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn format_weekday(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn rrules_round_trip() {
+        for rule in [
+            "FREQ=DAILY",
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=5",
+            "FREQ=MONTHLY;UNTIL=20240301T000000Z",
+            "FREQ=YEARLY;UNTIL=20240301",
+            "FREQ=DAILY;UNTIL=20240301T120000",
+        ] {
+            assert_eq!(Recurrence::from_rrule(rule).unwrap().to_rrule(), rule);
+        }
+    }
+
+    #[test]
+    fn until_keeps_its_form() {
+        let until = |rule: &str| Recurrence::from_rrule(rule).unwrap().until.unwrap();
+        assert!(matches!(
+            until("FREQ=DAILY;UNTIL=20240301T000000Z"),
+            TaskDate::Utc(_)
+        ));
+        assert!(matches!(
+            until("FREQ=DAILY;UNTIL=20240301"),
+            TaskDate::AllDay(_)
+        ));
+        assert!(matches!(
+            until("FREQ=DAILY;UNTIL=20240301T000000"),
+            TaskDate::Floating(_)
+        ));
+    }
+
+    #[test]
+    fn refuses_rules_it_cannot_schedule() {
+        assert_eq!(Recurrence::from_rrule("FREQ=HOURLY"), None);
+        assert_eq!(Recurrence::from_rrule("FREQ=MONTHLY;BYDAY=MO"), None);
+        assert_eq!(Recurrence::from_rrule("FREQ=DAILY;BYSETPOS=1"), None);
+        assert_eq!(Recurrence::from_rrule("INTERVAL=2"), None);
+        assert_eq!(Recurrence::from_rrule("FREQ=DAILY;INTERVAL=0"), None);
+    }
+
+    #[test]
+    fn finds_the_next_occurrence() {
+        let rule = |rule: &str| Recurrence::from_rrule(rule).unwrap();
+        // 2024-01-04 is a Thursday
+        let thursday = time("2024-01-04 09:00");
+        assert_eq!(
+            rule("FREQ=DAILY;INTERVAL=3").next_after(thursday),
+            Some(time("2024-01-07 09:00"))
+        );
+        assert_eq!(
+            rule("FREQ=WEEKLY;BYDAY=MO,TH").next_after(thursday),
+            Some(time("2024-01-08 09:00"))
+        );
+        assert_eq!(
+            rule("FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH").next_after(thursday),
+            Some(time("2024-01-16 09:00"))
+        );
+        // months without a 31st are skipped
+        assert_eq!(
+            rule("FREQ=MONTHLY").next_after(time("2024-01-31 09:00")),
+            Some(time("2024-03-31 09:00"))
+        );
+        assert_eq!(
+            rule("FREQ=YEARLY").next_after(time("2024-02-29 09:00")),
+            Some(time("2028-02-29 09:00"))
+        );
+        assert_eq!(rule("FREQ=DAILY;COUNT=1").next_after(thursday), None);
+        assert_eq!(rule("FREQ=DAILY;COUNT=2").advance().count, Some(1));
+    }
+
+    #[test]
+    fn ends_at_until() {
+        let rule = |rule: &str| Recurrence::from_rrule(rule).unwrap();
+        let at = |value: &str| TaskDate::Utc(DateTime::from_utc(time(value), Utc));
+        // a date-only UNTIL takes in the whole day
+        let all_day = rule("FREQ=DAILY;UNTIL=20240301");
+        assert!(!all_day.ends_before(&at("2024-03-01 23:00")));
+        assert!(all_day.ends_before(&at("2024-03-02 00:00")));
+        let utc = rule("FREQ=DAILY;UNTIL=20240301T080000Z");
+        assert!(!utc.ends_before(&at("2024-03-01 08:00")));
+        assert!(utc.ends_before(&at("2024-03-01 08:01")));
+        let floating = rule("FREQ=DAILY;UNTIL=20240301T090000");
+        assert!(!floating.ends_before(&TaskDate::Floating(time("2024-03-01 09:00"))));
+        assert!(floating.ends_before(&TaskDate::Floating(time("2024-03-01 09:01"))));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::alarm::Alarm;
+use crate::app::App;
+use crate::date::TaskDate;
+use crate::error::{Error, Result};
+use crate::task::Task;
+use crate::zone::Zone;
+use chrono::{DateTime, Utc};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+// Where reminders are sent once their alarm goes off
+pub trait Notifier {
+    fn notify(&self, reminder: &Reminder) -> Result<()>;
+}
+
+pub struct Reminder<'a> {
+    pub task: &'a Task,
+    pub alarm: &'a Alarm,
+    pub time: DateTime<Utc>,
+    pub zone: &'a Zone,
+}
+
+impl Reminder<'_> {
+    fn title(&self) -> String {
+        self.task.name().to_string()
+    }
+
+    fn body(&self) -> String {
+        match (self.task.due(), self.task.start_date()) {
+            (Some(due), _) => format!("due {}", due.format(self.zone)),
+            (None, Some(start)) => format!("starts {}", start.format(self.zone)),
+            (None, None) => "reminder".to_string(),
+        }
+    }
+}
+
+pub struct Stdout;
+
+impl Notifier for Stdout {
+    fn notify(&self, reminder: &Reminder) -> Result<()> {
+        println!(
+            "{} {} ({})",
+            reminder
+                .zone
+                .to_local(reminder.time)
+                .format("%Y-%m-%d %H:%M"),
+            reminder.title(),
+            reminder.body()
+        );
+        Ok(())
+    }
+}
+
+// A desktop notification command such as notify-send, given the title and
+// body as its arguments
+pub struct Desktop {
+    pub program: String,
+}
+
+impl Notifier for Desktop {
+    fn notify(&self, reminder: &Reminder) -> Result<()> {
+        run(Command::new(&self.program)
+            .arg(reminder.title())
+            .arg(reminder.body()))
+    }
+}
+
+// A script given the task's ID as its argument, with the details of the
+// reminder in TASK_* environment variables
+pub struct Script {
+    pub path: String,
+}
+
+impl Notifier for Script {
+    fn notify(&self, reminder: &Reminder) -> Result<()> {
+        let date = |date: Option<&TaskDate>| {
+            date.map(|date| date.format(reminder.zone))
+                .unwrap_or_default()
+        };
+        run(Command::new(&self.path)
+            .arg(reminder.task.id.to_string())
+            .env("TASK_ID", reminder.task.id.to_string())
+            .env("TASK_NAME", reminder.task.name())
+            .env("TASK_START", date(reminder.task.start_date()))
+            .env("TASK_DUE", date(reminder.task.due()))
+            .env("TASK_ALARM", reminder.alarm.to_string())
+            .env("TASK_REMINDER_TIME", reminder.time.to_rfc3339()))
+    }
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let program = command.get_program().to_string_lossy().to_string();
+    match command.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::Notify(format!("{} exited with {}", program, status))),
+        Err(error) => Err(Error::Notify(format!("{}: {}", program, error))),
+    }
+}
+
+// The reminders of open tasks whose alarm went off after `since`, up to and
+// including `until`
+pub fn due_reminders<'a>(
+    tasks: &'a [Task],
+    zone: &'a Zone,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Vec<Reminder<'a>> {
+    let mut reminders = Vec::new();
+    for task in tasks.iter().filter(|task| !task.is_closed()) {
+        for alarm in task.alarms() {
+            match alarm.time(task.start_date(), task.due(), zone) {
+                Some(time) if since < time && time <= until => reminders.push(Reminder {
+                    task,
+                    alarm,
+                    time,
+                    zone,
+                }),
+                _ => {}
+            }
+        }
+    }
+    reminders
+}
+
+// When the tasks were last checked for reminders, and last synced
+pub struct Watch {
+    last_check: DateTime<Utc>,
+    last_sync: Instant,
+    sync_interval: Duration,
+}
+
+impl Watch {
+    pub fn new(sync_interval: Duration) -> Self {
+        Self {
+            last_check: Utc::now(),
+            last_sync: Instant::now(),
+            sync_interval,
+        }
+    }
+
+    // Reads the tasks again and sends the reminders that came due since the
+    // last check. The journal is read each time for changes made by other
+    // commands, while the server is only synced once per sync interval.
+    pub async fn check(&mut self, app: &mut App, notifier: &dyn Notifier) {
+        let refreshed = match self.last_sync.elapsed() >= self.sync_interval {
+            true => {
+                self.last_sync = Instant::now();
+                app.reload().await
+            }
+            false => app.refresh(),
+        };
+        // the server may be unreachable for a while, so carry on with the
+        // state as it was last read
+        if let Err(error) = refreshed {
+            eprintln!("error: {}", error);
+        }
+        let now = Utc::now();
+        let tasks = app.get_present_state();
+        for reminder in due_reminders(&tasks, &app.zone, self.last_check, now) {
+            if let Err(error) = notifier.notify(&reminder) {
+                eprintln!("error: {}", error);
+            }
+        }
+        self.last_check = now;
+    }
+}
+
+// Checks the open tasks every interval and sends the reminders that came due
+// since the last check. Reminders due while the daemon was not running are
+// not sent.
+pub async fn watch(
+    app: &mut App,
+    notifier: &dyn Notifier,
+    interval: Duration,
+    sync_interval: Duration,
+) -> Result<()> {
+    let mut watch = Watch::new(sync_interval);
+    loop {
+        tokio::time::sleep(interval).await;
+        watch.check(app, notifier).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::Anchor;
+    use crate::app::tests::test_app;
+    use crate::app::{LogEntry, Message};
+    use crate::journal::{Journal, JournalEntry};
+    use chrono::NaiveDateTime;
+    use std::cell::RefCell;
+
+    // Keeps the names of the tasks it was sent reminders for
+    #[derive(Default)]
+    struct Recorder {
+        sent: RefCell<Vec<String>>,
+    }
+
+    impl Notifier for Recorder {
+        fn notify(&self, reminder: &Reminder) -> Result<()> {
+            self.sent.borrow_mut().push(reminder.title());
+            Ok(())
+        }
+    }
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::from_utc(
+            NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap(),
+            Utc,
+        )
+    }
+
+    fn task_with_alarm(name: &str, alarm: Alarm) -> Task {
+        Task::new(name.to_string()).add_alarm(alarm)
+    }
+
+    #[test]
+    fn reminds_of_alarms_since_the_last_check() {
+        let tasks = vec![
+            task_with_alarm("before", Alarm::Absolute(utc("2024-01-05 08:59"))),
+            task_with_alarm(
+                "at the last check",
+                Alarm::Absolute(utc("2024-01-05 09:00")),
+            ),
+            task_with_alarm("between", Alarm::Absolute(utc("2024-01-05 09:01"))),
+            task_with_alarm("now", Alarm::Absolute(utc("2024-01-05 09:02"))),
+            task_with_alarm("later", Alarm::Absolute(utc("2024-01-05 09:03"))),
+            task_with_alarm("done", Alarm::Absolute(utc("2024-01-05 09:01"))).mark_complete(),
+            task_with_alarm("no due date", Alarm::Relative(Anchor::Due, 0)),
+            task_with_alarm("due", Alarm::Relative(Anchor::Due, -3600))
+                .set_due_date(Some(TaskDate::Utc(utc("2024-01-05 10:01")))),
+        ];
+        let zone = Zone::utc();
+        let reminders = due_reminders(
+            &tasks,
+            &zone,
+            utc("2024-01-05 09:00"),
+            utc("2024-01-05 09:02"),
+        );
+        let names: Vec<String> = reminders.iter().map(|reminder| reminder.title()).collect();
+        assert_eq!(names, vec!["between", "now", "due"]);
+        assert_eq!(reminders[2].time, utc("2024-01-05 09:01"));
+    }
+
+    #[tokio::test]
+    async fn check_picks_up_changes_from_other_commands() {
+        let now = Utc::now();
+        let soon = task_with_alarm("soon", Alarm::Absolute(now - chrono::Duration::seconds(1)));
+        let (mut app, folder) = test_app("watch", &[soon]).await;
+        let recorder = Recorder::default();
+        let mut watch = Watch::new(Duration::from_secs(3600));
+        watch.last_check = now - chrono::Duration::minutes(1);
+        watch.check(&mut app, &recorder).await;
+        assert_eq!(*recorder.sent.borrow(), vec!["soon"]);
+
+        // as `tasks add` run elsewhere would
+        let added = task_with_alarm("added", Alarm::Absolute(Utc::now()));
+        Journal::new(&folder)
+            .append(&JournalEntry::Add(Box::new(LogEntry {
+                sequence: 1,
+                timestamp: Utc::now(),
+                device: "other".to_string(),
+                message: Message::AddTask(Box::new(added)),
+            })))
+            .unwrap();
+        watch.check(&mut app, &recorder).await;
+        assert_eq!(*recorder.sent.borrow(), vec!["soon", "added"]);
+        // the cache is saved when the app is dropped
+        drop(app);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -1,186 +1,483 @@
-use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use crate::alarm::Alarm;
+use crate::config::TagMapping;
+use crate::date::TaskDate;
+use crate::duration::{format_duration, format_span, parse_duration};
+use crate::error::{Error, Result};
+use crate::recurrence::Recurrence;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use ical::parser::ical::component::IcalAlarm;
 use ical::property::Property;
 use kitchen_fridge::Item;
 use rand::*;
-use std::{fmt::Display, str::FromStr};
-use strum_macros::{Display, EnumString, ToString};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use strum_macros::{Display, EnumString};
 use url::Url;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Task {
     pub id: Uuid,
+    // the UID of an item created by another client, when it is not the ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uid: Option<String>,
+    // where the item lives on the server, once it has been loaded or added there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<Url>,
     creation_date: DateTime<Utc>,
     last_modified: DateTime<Utc>,
     name: String,
-    completed: CompletionStatus,
-    start_date: Option<NaiveDateTime>,
-    due: Option<NaiveDateTime>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(alias = "completed")]
+    status: Status,
+    // PERCENT-COMPLETE, for tasks that are still open
+    #[serde(default)]
+    percent_complete: Option<u8>,
+    // the RFC 5545 PRIORITY, 1 (highest) to 9 (lowest)
+    #[serde(default)]
+    priority: Option<u8>,
+    start_date: Option<TaskDate>,
+    due: Option<TaskDate>,
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alarms: Vec<Alarm>,
+    // VALARM components set by other clients, written back as is while their
+    // alarm is kept
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    other_alarms: Vec<Vec<StoredProperty>>,
+    // estimated and recorded time, in seconds
+    #[serde(default)]
+    estimate: Option<i64>,
+    #[serde(default)]
+    time_spent: i64,
+    #[serde(default)]
+    timer_started: Option<DateTime<Utc>>,
     contexts: Vec<String>,
     areas: Vec<String>,
     projects: Vec<String>,
+    // plain categories, as set by other clients
+    #[serde(default)]
+    tags: Vec<String>,
     money_needed: bool,
     time_of_day: Option<TimeOfDay>,
     weather: Option<Weather>,
     parent_task: Option<Uuid>,
+    // UIDs of linked tasks as they were read, for links whose UID is not the
+    // task ID, so that links to tasks we have not loaded are written back
+    // unchanged
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    linked_uids: BTreeMap<Uuid, String>,
+    // tasks that must be finished before this one can be done
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    depends_on: Vec<Uuid>,
+    // properties set by other clients that we do not manage, written back as is
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<StoredProperty>,
 }
 
 impl Task {
     pub fn new(name: String) -> Self {
         Self {
             id: uuid::Builder::from_random_bytes(rand::thread_rng().gen()).into_uuid(),
+            uid: None,
+            url: None,
             creation_date: chrono::offset::Utc::now(),
             last_modified: chrono::offset::Utc::now(),
             name,
-            completed: CompletionStatus::Incomplete,
+            description: None,
+            status: Status::NeedsAction,
+            percent_complete: None,
+            priority: None,
             start_date: None,
             due: None,
+            recurrence: None,
+            alarms: Vec::new(),
+            other_alarms: Vec::new(),
+            estimate: None,
+            time_spent: 0,
+            timer_started: None,
             contexts: Vec::new(),
             areas: Vec::new(),
             projects: Vec::new(),
+            tags: Vec::new(),
             money_needed: false,
             time_of_day: None,
             weather: None,
             parent_task: None,
+            linked_uids: BTreeMap::new(),
+            depends_on: Vec::new(),
+            properties: Vec::new(),
         }
     }
-    pub fn from_item(item: Item) -> Self {
-        let id = Uuid::parse_str(item.uid())
-            .expect(format!("{} is an Invalid UUID", item.uid()).as_str());
-        let creation_date = item
-            .creation_date()
-            .expect("Item has no creation date")
-            .clone();
-        let last_modified = item.last_modified().clone();
+    pub fn from_item(item: Item) -> Result<Self> {
+        if !item.is_task() {
+            return Err(Error::Parse(format!("{} is not a task", item.url())));
+        }
+        if item.uid().trim().is_empty() {
+            return Err(Error::Parse(format!("{} has no UID", item.url())));
+        }
+        let id = Task::id_for_uid(item.uid());
+        // UUIDs in other forms, e.g. uppercase, are kept as written so that
+        // the item is still recognised when it is synced
+        let uid = match item.uid() == id.to_string() {
+            true => None,
+            false => Some(item.uid().to_string()),
+        };
+        let last_modified = *item.last_modified();
+        let creation_date = item.creation_date().copied().unwrap_or(last_modified);
         let name = item.name().to_string();
-        let completed =
-            CompletionStatus::from_kitchen_fridge(item.unwrap_task().completion_status());
+        let description = item.get_attribute_from_item("DESCRIPTION");
+        let status = Status::from_kitchen_fridge(
+            item.unwrap_task().completion_status(),
+            item.get_attribute_from_item("STATUS")
+                .or_else(|| item.get_attribute_from_item("X-TASKS-STATUS"))
+                .map(|status| status.trim().to_uppercase())
+                .as_deref(),
+        );
+        let percent_complete = item
+            .get_attribute_from_item("PERCENT-COMPLETE")
+            .and_then(|value| value.trim().parse::<u8>().ok())
+            .filter(|percent| *percent <= 100);
+        let priority = item
+            .get_attribute_from_item("PRIORITY")
+            .and_then(|value| value.trim().parse::<u8>().ok())
+            .filter(|priority| (1..=9).contains(priority));
         let start_date = item.get_date_from_item_attribute("DTSTART");
         let due = item.get_date_from_item_attribute("DUE");
-        let contexts = item.get_attribute_from_tag("CONTEXT ");
-        let areas = item.get_attribute_from_tag("AREA ");
-        let projects = item.get_attribute_from_tag("PROJECT ");
-        let money_needed = item.get_attribute_from_tag("MONEYNEEDED ").len() > 0;
-        let time_of_day: Option<TimeOfDay> = {
-            let time_of_day_tags = &item.get_attribute_from_tag("TIMEOFDAY ");
-            if time_of_day_tags.len() > 0 {
-                TimeOfDay::from_str(&time_of_day_tags[0]).ok()
-            } else {
-                None
+        let recurrence = item
+            .get_attribute_from_item("RRULE")
+            .and_then(|rule| Recurrence::from_rrule(&rule))
+            .map(|recurrence| Recurrence {
+                from_completion: item
+                    .get_attribute_from_item("X-TASKS-RECUR-FROM")
+                    .is_some_and(|from| from.eq_ignore_ascii_case("COMPLETION")),
+                ..recurrence
+            });
+        let mut alarms = Vec::new();
+        let mut other_alarms = Vec::new();
+        for component in item.unwrap_task().alarms() {
+            let properties: Vec<StoredProperty> = component
+                .properties
+                .iter()
+                .map(StoredProperty::from)
+                .collect();
+            let alarm = component_alarm(&properties);
+            if let Some(alarm) = &alarm {
+                if !alarms.contains(alarm) {
+                    alarms.push(alarm.clone());
+                }
             }
-        };
-        let weather: Option<Weather> = {
-            let weather_tags = &item.get_attribute_from_tag("WEATHER ");
-            if weather_tags.len() > 0 {
-                Weather::from_str(&weather_tags[0]).ok()
-            } else {
-                None
+            // the components we write ourselves are written again from the alarm
+            let ours = match &alarm {
+                Some(alarm) => properties == alarm_component(&name, alarm),
+                None => false,
+            };
+            if !ours {
+                other_alarms.push(properties);
             }
-        };
-        let parent_task = item.get_parent_uuid();
-        Task {
+        }
+        let estimate = item
+            .get_attribute_from_item("X-TASKS-ESTIMATE")
+            .and_then(|value| parse_duration(value.trim()))
+            .filter(|estimate| *estimate >= 0);
+        let time_spent = item
+            .get_attribute_from_item("X-TASKS-TIME-SPENT")
+            .and_then(|value| parse_duration(value.trim()))
+            .unwrap_or(0)
+            .max(0);
+        let timer_started = item
+            .get_attribute_from_item("X-TASKS-TIMER-STARTED")
+            .and_then(|value| NaiveDateTime::parse_from_str(value.trim(), "%Y%m%dT%H%M%SZ").ok())
+            .map(|time| DateTime::from_utc(time, Utc));
+        // items written before the X-TASKS- properties keep these in CATEGORIES
+        let contexts = merge_unique(
+            item.get_attributes_from_item("X-TASKS-CONTEXT"),
+            item.get_attribute_from_tag("CONTEXT "),
+        );
+        let areas = merge_unique(
+            item.get_attributes_from_item("X-TASKS-AREA"),
+            item.get_attribute_from_tag("AREA "),
+        );
+        let projects = merge_unique(
+            item.get_attributes_from_item("X-TASKS-PROJECT"),
+            item.get_attribute_from_tag("PROJECT "),
+        );
+        let money_needed = item
+            .get_attribute_from_item("X-TASKS-MONEY-NEEDED")
+            .map(|value| value.eq_ignore_ascii_case("TRUE"))
+            .unwrap_or_else(|| !item.get_attribute_from_tag("MONEYNEEDED ").is_empty());
+        let time_of_day = item
+            .get_attribute_from_item("X-TASKS-TIME-OF-DAY")
+            .and_then(|value| TimeOfDay::from_property_value(&value))
+            .or_else(|| {
+                item.get_tags()
+                    .iter()
+                    .find(|tag| tag.starts_with("TIMEOFDAY "))
+                    .and_then(|tag| TimeOfDay::from_str(tag).ok())
+            });
+        let weather = item
+            .get_attribute_from_item("X-TASKS-WEATHER")
+            .and_then(|value| Weather::from_property_value(&value))
+            .or_else(|| {
+                item.get_tags()
+                    .iter()
+                    .find(|tag| tag.starts_with("WEATHER "))
+                    .and_then(|tag| Weather::from_str(tag).ok())
+            });
+        let tags = item
+            .get_tags()
+            .into_iter()
+            .filter(|tag| !tag.is_empty() && !is_legacy_category(tag))
+            .fold(Vec::new(), |tags, tag| merge_unique(tags, vec![tag]));
+        let mut linked_uids = BTreeMap::new();
+        let parent_uid = item.get_parent_uid();
+        let parent_task = parent_uid.as_deref().map(Task::id_for_uid);
+        if let (Some(id), Some(uid)) = (parent_task, parent_uid) {
+            if uid != id.to_string() {
+                linked_uids.insert(id, uid);
+            }
+        }
+        let mut depends_on = Vec::new();
+        for uid in item.get_dependency_uids() {
+            let id = Task::id_for_uid(&uid);
+            if uid != id.to_string() {
+                linked_uids.insert(id, uid);
+            }
+            if !depends_on.contains(&id) {
+                depends_on.push(id);
+            }
+        }
+        let properties = item
+            .get_properties()
+            .into_iter()
+            .filter_map(|property| match property.name.as_str() {
+                "DESCRIPTION" => None,
+                "STATUS" => None,
+                "PERCENT-COMPLETE" => None,
+                "PRIORITY" if priority.is_some() => None,
+                "DTSTART" if start_date.is_some() => None,
+                "DUE" if due.is_some() => None,
+                "RRULE" if recurrence.is_some() => None,
+                "RELATED-TO" if is_parent_relation(property) => None,
+                "RELATED-TO" if is_dependency_relation(property) => None,
+                name if name.starts_with("X-TASKS-") => None,
+                "CATEGORIES" => None,
+                _ => Some(StoredProperty::from(property)),
+            })
+            .collect();
+        Ok(Task {
             id,
+            uid,
+            url: Some(item.url().clone()),
             creation_date,
             last_modified,
             name,
-            completed,
+            description,
+            status,
+            percent_complete,
+            priority,
             start_date,
             due,
+            recurrence,
+            alarms,
+            other_alarms,
+            estimate,
+            time_spent,
+            timer_started,
             contexts,
             areas,
             projects,
+            tags,
             money_needed,
             time_of_day,
             weather,
             parent_task,
-        }
+            linked_uids,
+            depends_on,
+            properties,
+        })
     }
 
-    pub fn to_item(&self, calendar_url: &Url) -> Item {
+    // uid_of gives the UID of another loaded task, which differs from its ID
+    // for tasks created by other clients
+    pub fn to_item(&self, calendar_url: &Url, uid_of: &dyn Fn(Uuid) -> String) -> Item {
         Item::Task(kitchen_fridge::Task::new_with_parameters(
             self.name.clone(),
-            self.id.to_string(),
-            kitchen_fridge::utils::random_url(calendar_url),
-            self.completed.to_kitchen_fridge(),
+            self.uid(),
+            self.url
+                .clone()
+                .unwrap_or_else(|| kitchen_fridge::utils::random_url(calendar_url)),
+            self.status.to_kitchen_fridge(),
             kitchen_fridge::item::SyncStatus::NotSynced,
             Some(self.creation_date),
             self.last_modified,
             kitchen_fridge::ical::default_prod_id(),
-            self.get_item_properties(),
+            self.merge_properties(self.get_item_properties(uid_of)),
+            self.get_item_alarms(),
         ))
     }
 
-    fn get_item_properties(&self) -> Vec<Property> {
+    // Components from other clients are written back unless their alarm was
+    // removed, and the other alarms get components of our own
+    fn get_item_alarms(&self) -> Vec<IcalAlarm> {
+        let mut components = Vec::new();
+        let mut written = Vec::new();
+        for component in self.other_alarms.iter() {
+            match component_alarm(component) {
+                Some(alarm) if !self.alarms.contains(&alarm) => continue,
+                Some(alarm) => written.push(alarm),
+                None => {}
+            }
+            components.push(component.clone());
+        }
+        for alarm in self.alarms.iter() {
+            if !written.contains(alarm) {
+                components.push(alarm_component(&self.name, alarm));
+            }
+        }
+        components
+            .into_iter()
+            .map(|properties| IcalAlarm {
+                properties: properties.into_iter().map(Property::from).collect(),
+            })
+            .collect()
+    }
+
+    // Our own properties replace any of the same name that another client set,
+    // except for RELATED-TO links other than the parent, which are kept.
+    fn merge_properties(&self, managed: Vec<Property>) -> Vec<Property> {
+        let mut properties: Vec<Property> = Vec::new();
+        for property in self.properties.iter() {
+            let replaced = match property.name.as_str() {
+                "RELATED-TO" => false,
+                name => managed.iter().any(|managed| managed.name == name),
+            };
+            if !replaced {
+                properties.push(property.clone().into());
+            }
+        }
+        properties.extend(managed);
+        properties
+    }
+
+    fn linked_uid(&self, task_id: Uuid, uid_of: &dyn Fn(Uuid) -> String) -> String {
+        match self.linked_uids.get(&task_id) {
+            Some(uid) => uid.clone(),
+            None => uid_of(task_id),
+        }
+    }
+
+    fn get_item_properties(&self, uid_of: &dyn Fn(Uuid) -> String) -> Vec<Property> {
         let mut properties: Vec<Property> = Vec::new();
         if let Some(parent_id) = self.parent_task {
             properties.push(Property {
                 name: "RELATED-TO".to_owned(),
                 params: None,
-                value: Some(parent_id.to_string()),
+                value: Some(self.linked_uid(parent_id, uid_of)),
             })
         }
-
-        if let Some(date) = self.start_date {
+        for dependency_id in self.depends_on.iter() {
             properties.push(Property {
-                name: "DTSTART".to_owned(),
-                //later you will not want the timezone to be specifically sydney australia
-                params: Some(vec![(
-                    "TZID".to_owned(),
-                    vec!["Australia/Sydney".to_owned()],
-                )]),
-                value: Some(date.format("%Y%m%dT%H%M%S").to_string()),
+                name: "RELATED-TO".to_owned(),
+                params: Some(vec![("RELTYPE".to_owned(), vec!["DEPENDS-ON".to_owned()])]),
+                value: Some(self.linked_uid(*dependency_id, uid_of)),
             })
         }
-        if let Some(date) = self.due {
+
+        if let Some(date) = &self.start_date {
+            properties.push(date.to_property("DTSTART"));
+        }
+        if let Some(date) = &self.due {
+            properties.push(date.to_property("DUE"));
+        }
+        // kitchen-fridge writes STATUS itself for needs-action and completed tasks
+        if let Status::InProcess | Status::Cancelled = self.status {
+            properties.push(text_property("STATUS", self.status.to_property_value()));
+        }
+        // kitchen-fridge writes PERCENT-COMPLETE itself for completed tasks
+        if let (Some(percent), Status::NeedsAction | Status::InProcess) =
+            (self.percent_complete, self.status)
+        {
+            properties.push(Property {
+                name: "PERCENT-COMPLETE".to_owned(),
+                params: None,
+                value: Some(percent.to_string()),
+            });
+        }
+        if let Some(recurrence) = &self.recurrence {
             properties.push(Property {
-                name: "DUE".to_owned(),
-                params: Some(vec![(
-                    "TZID".to_owned(),
-                    vec!["Australia/Sydney".to_owned()],
-                )]),
-                value: Some(date.format("%Y%m%dT%H%M%S").to_string()),
+                name: "RRULE".to_owned(),
+                params: None,
+                value: Some(recurrence.to_rrule()),
+            });
+            if recurrence.from_completion {
+                properties.push(text_property("X-TASKS-RECUR-FROM", "COMPLETION"));
+            }
+        }
+        if let Some(description) = &self.description {
+            properties.push(text_property("DESCRIPTION", description));
+        }
+        if let Some(priority) = self.priority {
+            properties.push(Property {
+                name: "PRIORITY".to_owned(),
+                params: None,
+                value: Some(priority.to_string()),
             })
         }
 
-        let weather_string = match &self.weather {
-            Some(weather) => weather.to_string() + ",",
-            None => "".to_string(),
-        };
-        let time_of_day_string = match &self.time_of_day {
-            Some(time_of_day) => time_of_day.to_string() + ",",
-            None => "".to_string(),
-        };
-        let money_needed_string = match &self.money_needed {
-            true => "MONEYNEEDED  true,".to_string(),
-            false => "".to_string(),
-        };
-        let contexts_string = self.contexts.iter().fold("".to_string(), |acc, context| {
-            acc + &format!("CONTEXT  {}, ", context)
-        });
-        let areas_string = self.areas.iter().fold("".to_string(), |acc, area| {
-            acc + &format!("AREA  {},", area)
-        });
-        let projects_string = self.projects.iter().fold("".to_string(), |acc, project| {
-            acc + &format!("PROJECT  {},", project)
-        });
-
-        let categories_string = format!(
-            "{}{}{}{}{}{}",
-            weather_string,
-            time_of_day_string,
-            contexts_string,
-            areas_string,
-            projects_string,
-            money_needed_string,
-        )
-        .trim()
-        .trim_end_matches(",")
-        .to_string();
-        properties.push(Property {
-            name: "CATEGORIES".to_owned(),
-            params: None,
-            value: Some(categories_string),
-        });
+        for context in self.contexts.iter() {
+            properties.push(text_property("X-TASKS-CONTEXT", context));
+        }
+        for area in self.areas.iter() {
+            properties.push(text_property("X-TASKS-AREA", area));
+        }
+        for project in self.projects.iter() {
+            properties.push(text_property("X-TASKS-PROJECT", project));
+        }
+        if let Some(weather) = &self.weather {
+            properties.push(text_property(
+                "X-TASKS-WEATHER",
+                &weather.to_property_value(),
+            ));
+        }
+        if let Some(time_of_day) = &self.time_of_day {
+            properties.push(text_property(
+                "X-TASKS-TIME-OF-DAY",
+                &time_of_day.to_property_value(),
+            ));
+        }
+        if self.money_needed {
+            properties.push(text_property("X-TASKS-MONEY-NEEDED", "TRUE"));
+        }
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self.tags.iter().map(|tag| escape_text(tag)).collect();
+            properties.push(Property {
+                name: "CATEGORIES".to_owned(),
+                params: None,
+                value: Some(tags.join(",")),
+            });
+        }
+        if let Some(estimate) = self.estimate {
+            properties.push(text_property(
+                "X-TASKS-ESTIMATE",
+                &format_duration(estimate),
+            ));
+        }
+        if self.time_spent > 0 {
+            properties.push(text_property(
+                "X-TASKS-TIME-SPENT",
+                &format_duration(self.time_spent),
+            ));
+        }
+        if let Some(started) = self.timer_started {
+            properties.push(text_property(
+                "X-TASKS-TIMER-STARTED",
+                &started.format("%Y%m%dT%H%M%SZ").to_string(),
+            ));
+        }
         properties
     }
 
@@ -191,16 +488,43 @@ impl Task {
         }
     }
 
+    // A running timer is stopped, so the time up to completion is counted
     pub fn mark_complete(&self) -> Self {
+        let now = chrono::offset::Utc::now();
         Self {
-            completed: CompletionStatus::Completed(Some(chrono::offset::Utc::now())),
-            ..self.clone()
+            status: Status::Completed(Some(now)),
+            percent_complete: None,
+            ..self.stop_timer(now)
         }
         .modify()
     }
     pub fn mark_incomplete(&self) -> Self {
         Self {
-            completed: CompletionStatus::Incomplete,
+            status: Status::NeedsAction,
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    pub fn mark_in_process(&self) -> Self {
+        Self {
+            status: Status::InProcess,
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    pub fn mark_cancelled(&self) -> Self {
+        Self {
+            status: Status::Cancelled,
+            ..self.stop_timer(chrono::offset::Utc::now())
+        }
+        .modify()
+    }
+
+    pub fn set_percent_complete(&self, percent_complete: Option<u8>) -> Self {
+        Self {
+            percent_complete,
             ..self.clone()
         }
         .modify()
@@ -214,7 +538,15 @@ impl Task {
         .modify()
     }
 
-    pub fn set_start_date(&self, start_date: Option<NaiveDateTime>) -> Self {
+    pub fn set_description(&self, description: Option<String>) -> Self {
+        Self {
+            description,
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    pub fn set_start_date(&self, start_date: Option<TaskDate>) -> Self {
         Self {
             start_date,
             ..self.clone()
@@ -222,7 +554,7 @@ impl Task {
         .modify()
     }
 
-    pub fn set_due_date(&self, due: Option<NaiveDateTime>) -> Self {
+    pub fn set_due_date(&self, due: Option<TaskDate>) -> Self {
         Self {
             due,
             ..self.clone()
@@ -230,6 +562,69 @@ impl Task {
         .modify()
     }
 
+    pub fn set_priority(&self, priority: Priority) -> Self {
+        Self {
+            priority: priority.to_value(),
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    pub fn set_recurrence(&self, recurrence: Option<Recurrence>) -> Self {
+        Self {
+            recurrence,
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    // The task to do after this recurring one is completed, with its dates
+    // moved to the next occurrence. Its ID follows from this task's, so that
+    // replaying the completion always produces the same instance.
+    pub fn next_instance(&self, completed_at: DateTime<Utc>) -> Option<Self> {
+        let recurrence = self.recurrence.as_ref()?;
+        // occurrences are counted in the wall time of the task's own dates
+        let date = self.due.as_ref().or(self.start_date.as_ref());
+        let completed_at = match date {
+            Some(date) => date.wall_time_at(completed_at),
+            None => completed_at.naive_utc(),
+        };
+        let anchor = date.map_or(completed_at, TaskDate::wall_time);
+        let next = match recurrence.from_completion {
+            true => recurrence.next_after(completed_at.date().and_time(anchor.time()))?,
+            false => recurrence.next_after(anchor)?,
+        };
+        let shift = next - anchor;
+        let next_date = match date {
+            Some(date) => date.shifted(shift),
+            None => TaskDate::Utc(DateTime::from_utc(next, Utc)),
+        };
+        if recurrence.ends_before(&next_date) {
+            return None;
+        }
+        let now = chrono::offset::Utc::now();
+        Some(Self {
+            id: Task::id_for_uid(&format!("{}+1", self.uid())),
+            uid: None,
+            url: None,
+            creation_date: now,
+            last_modified: now,
+            status: Status::NeedsAction,
+            percent_complete: None,
+            time_spent: 0,
+            timer_started: None,
+            start_date: self.start_date.as_ref().map(|date| date.shifted(shift)),
+            due: self.due.as_ref().map(|date| date.shifted(shift)),
+            alarms: self
+                .alarms
+                .iter()
+                .map(|alarm| alarm.shifted(shift))
+                .collect(),
+            recurrence: Some(recurrence.advance()),
+            ..self.clone()
+        })
+    }
+
     pub fn set_money_needed(&self, money_needed: bool) -> Self {
         Self {
             money_needed,
@@ -256,7 +651,9 @@ impl Task {
 
     pub fn add_context(&self, new_context: String) -> Self {
         let mut output = self.clone();
-        output.contexts.push(new_context);
+        if !output.contexts.contains(&new_context) {
+            output.contexts.push(new_context);
+        }
         output.modify()
     }
 
@@ -275,7 +672,9 @@ impl Task {
 
     pub fn add_area(&self, new_area: String) -> Self {
         let mut output = self.clone();
-        output.areas.push(new_area);
+        if !output.areas.contains(&new_area) {
+            output.areas.push(new_area);
+        }
         output.modify()
     }
 
@@ -293,13 +692,15 @@ impl Task {
     }
     pub fn add_project(&self, new_project: String) -> Self {
         let mut output = self.clone();
-        output.projects.push(new_project);
+        if !output.projects.contains(&new_project) {
+            output.projects.push(new_project);
+        }
         output.modify()
     }
 
     pub fn remove_project(&self, project: String) -> Self {
         Self {
-            contexts: self
+            projects: self
                 .clone()
                 .projects
                 .into_iter()
@@ -310,6 +711,131 @@ impl Task {
         .modify()
     }
 
+    pub fn add_tag(&self, new_tag: String) -> Self {
+        let mut output = self.clone();
+        if !output.tags.contains(&new_tag) {
+            output.tags.push(new_tag);
+        }
+        output.modify()
+    }
+
+    pub fn remove_tag(&self, tag: String) -> Self {
+        Self {
+            tags: self
+                .clone()
+                .tags
+                .into_iter()
+                .filter(|existing_tag| &tag != existing_tag)
+                .collect(),
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    pub fn set_estimate(&self, estimate: Option<i64>) -> Self {
+        Self {
+            estimate,
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    pub fn start_timer(&self, at: DateTime<Utc>) -> Self {
+        Self {
+            timer_started: Some(at),
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    pub fn stop_timer(&self, at: DateTime<Utc>) -> Self {
+        let elapsed = self
+            .timer_started
+            .map_or(0, |started| (at - started).num_seconds().max(0));
+        Self {
+            time_spent: self.time_spent + elapsed,
+            timer_started: None,
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    pub fn add_alarm(&self, alarm: Alarm) -> Self {
+        let mut output = self.clone();
+        if !output.alarms.contains(&alarm) {
+            output.alarms.push(alarm);
+        }
+        output.modify()
+    }
+
+    pub fn remove_alarm(&self, alarm: &Alarm) -> Self {
+        Self {
+            alarms: self
+                .alarms
+                .iter()
+                .filter(|existing| *existing != alarm)
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+        .modify()
+    }
+
+    // Tags named in the profile's tag mapping are read as contexts and areas,
+    // and written back as the same tags so other clients keep seeing them.
+    pub fn with_mapped_tags(&self, mapping: &TagMapping) -> Self {
+        let mut output = Self {
+            tags: Vec::new(),
+            ..self.clone()
+        };
+        for tag in self.tags.iter() {
+            if let Some(context) = mapping.contexts.get(tag) {
+                output.contexts = merge_unique(output.contexts, vec![context.clone()]);
+            } else if let Some(area) = mapping.areas.get(tag) {
+                output.areas = merge_unique(output.areas, vec![area.clone()]);
+            } else {
+                output.tags.push(tag.clone());
+            }
+        }
+        output
+    }
+
+    pub fn with_unmapped_tags(&self, mapping: &TagMapping) -> Self {
+        let mut output = self.clone();
+        for (tag, context) in mapping.contexts.iter() {
+            if self.contexts.contains(context) && !output.tags.contains(tag) {
+                output.tags.push(tag.clone());
+            }
+        }
+        for (tag, area) in mapping.areas.iter() {
+            if self.areas.contains(area) && !output.tags.contains(tag) {
+                output.tags.push(tag.clone());
+            }
+        }
+        output
+    }
+
+    pub fn add_dependency(&self, dependency_id: Uuid) -> Self {
+        let mut output = self.clone();
+        if !output.depends_on.contains(&dependency_id) {
+            output.depends_on.push(dependency_id);
+        }
+        output.modify()
+    }
+
+    pub fn remove_dependency(&self, dependency_id: Uuid) -> Self {
+        Self {
+            depends_on: self
+                .depends_on
+                .iter()
+                .filter(|id| **id != dependency_id)
+                .copied()
+                .collect(),
+            ..self.clone()
+        }
+        .modify()
+    }
+
     pub fn set_parent_task(&self, parent_task: Option<Uuid>) -> Self {
         Self {
             parent_task,
@@ -317,36 +843,489 @@ impl Task {
         }
         .modify()
     }
+
+    // Items from other clients often have UIDs that are not UUIDs, so those
+    // are hashed into a stable internal ID that the journal can refer to.
+    pub fn id_for_uid(uid: &str) -> Uuid {
+        if let Ok(id) = Uuid::parse_str(uid) {
+            return id;
+        }
+        // 128 bit FNV-1a
+        let hash = uid
+            .bytes()
+            .fold(0x6c62272e07bb014262b821756295c58d_u128, |hash, byte| {
+                (hash ^ byte as u128).wrapping_mul(0x0000000001000000000000000000013b)
+            });
+        Uuid::from_u128(hash)
+    }
+
+    pub fn url(&self) -> Option<&Url> {
+        self.url.as_ref()
+    }
+
+    pub fn with_url(&self, url: Url) -> Self {
+        Self {
+            url: Some(url),
+            ..self.clone()
+        }
+    }
+
+    pub fn uid(&self) -> String {
+        self.uid.clone().unwrap_or_else(|| self.id.to_string())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn is_completed(&self) -> bool {
+        matches!(self.status, Status::Completed(_))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.status == Status::Cancelled
+    }
+
+    // completed or cancelled, so no longer to be done
+    pub fn is_closed(&self) -> bool {
+        self.is_completed() || self.is_cancelled()
+    }
+
+    pub fn percent_complete(&self) -> Option<u8> {
+        self.percent_complete
+    }
+
+    pub fn completion_date(&self) -> Option<DateTime<Utc>> {
+        match self.status {
+            Status::Completed(date) => date,
+            _ => None,
+        }
+    }
+
+    pub fn creation_date(&self) -> DateTime<Utc> {
+        self.creation_date
+    }
+
+    pub fn last_modified(&self) -> DateTime<Utc> {
+        self.last_modified
+    }
+
+    pub fn start_date(&self) -> Option<&TaskDate> {
+        self.start_date.as_ref()
+    }
+
+    pub fn due(&self) -> Option<&TaskDate> {
+        self.due.as_ref()
+    }
+
+    pub fn alarms(&self) -> &[Alarm] {
+        &self.alarms
+    }
+
+    pub fn estimate(&self) -> Option<i64> {
+        self.estimate
+    }
+
+    pub fn timer_started(&self) -> Option<DateTime<Utc>> {
+        self.timer_started
+    }
+
+    // recorded time, plus that of a running timer up to `now`
+    pub fn time_spent(&self, now: DateTime<Utc>) -> i64 {
+        self.time_spent
+            + self
+                .timer_started
+                .map_or(0, |started| (now - started).num_seconds().max(0))
+    }
+
+    pub fn recurrence(&self) -> Option<&Recurrence> {
+        self.recurrence.as_ref()
+    }
+
+    pub fn contexts(&self) -> &[String] {
+        &self.contexts
+    }
+
+    pub fn areas(&self) -> &[String] {
+        &self.areas
+    }
+
+    pub fn projects(&self) -> &[String] {
+        &self.projects
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn priority(&self) -> Priority {
+        Priority::from_value(self.priority)
+    }
+
+    pub fn money_needed(&self) -> bool {
+        self.money_needed
+    }
+
+    pub fn time_of_day(&self) -> Option<&TimeOfDay> {
+        self.time_of_day.as_ref()
+    }
+
+    pub fn weather(&self) -> Option<&Weather> {
+        self.weather.as_ref()
+    }
+
+    pub fn parent_task(&self) -> Option<Uuid> {
+        self.parent_task
+    }
+
+    pub fn depends_on(&self) -> &[Uuid] {
+        &self.depends_on
+    }
+
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let date = |date: &Option<TaskDate>| match date {
+            Some(date) => date.to_string(),
+            None => "".to_string(),
+        };
+        vec![
+            ("name", self.name.clone()),
+            ("description", self.description.clone().unwrap_or_default()),
+            ("status", self.status.to_string()),
+            (
+                "percent complete",
+                self.percent_complete
+                    .map(|percent| percent.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("priority", self.priority().to_string()),
+            ("start", date(&self.start_date)),
+            ("due", date(&self.due)),
+            (
+                "repeat",
+                self.recurrence
+                    .as_ref()
+                    .map(|recurrence| recurrence.to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "reminders",
+                self.alarms
+                    .iter()
+                    .map(|alarm| alarm.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ),
+            (
+                "estimate",
+                self.estimate.map(format_span).unwrap_or_default(),
+            ),
+            ("time spent", format_span(self.time_spent)),
+            (
+                "timer started",
+                self.timer_started
+                    .map(|started| started.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default(),
+            ),
+            ("contexts", self.contexts.join(", ")),
+            ("areas", self.areas.join(", ")),
+            ("projects", self.projects.join(", ")),
+            ("tags", self.tags.join(", ")),
+            ("money needed", self.money_needed.to_string()),
+            (
+                "time of day",
+                self.time_of_day
+                    .as_ref()
+                    .map(|time_of_day| format!("{:?}", time_of_day))
+                    .unwrap_or_default(),
+            ),
+            (
+                "weather",
+                self.weather
+                    .as_ref()
+                    .map(|weather| format!("{:?}", weather))
+                    .unwrap_or_default(),
+            ),
+            (
+                "parent",
+                self.parent_task
+                    .map(|parent| parent.to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "depends on",
+                self.depends_on
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ),
+        ]
+    }
+}
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+struct StoredProperty {
+    name: String,
+    params: Option<Vec<(String, Vec<String>)>>,
+    value: Option<String>,
+}
+
+impl From<&Property> for StoredProperty {
+    fn from(property: &Property) -> Self {
+        Self {
+            name: property.name.clone(),
+            params: property.params.clone(),
+            value: property.value.clone(),
+        }
+    }
+}
+
+impl From<StoredProperty> for Property {
+    fn from(property: StoredProperty) -> Self {
+        Self {
+            name: property.name,
+            params: property.params,
+            value: property.value,
+        }
+    }
+}
+
+// The alarm set by the TRIGGER of a VALARM component
+fn component_alarm(component: &[StoredProperty]) -> Option<Alarm> {
+    let trigger = component
+        .iter()
+        .find(|property| property.name == "TRIGGER")?;
+    Alarm::from_trigger(&trigger.clone().into())
+}
+
+// The VALARM component we write for an alarm
+fn alarm_component(name: &str, alarm: &Alarm) -> Vec<StoredProperty> {
+    [
+        text_property("ACTION", "DISPLAY"),
+        text_property("DESCRIPTION", name),
+        alarm.to_trigger(),
+    ]
+    .iter()
+    .map(StoredProperty::from)
+    .collect()
+}
+
+fn text_property(name: &str, value: &str) -> Property {
+    Property {
+        name: name.to_owned(),
+        params: None,
+        value: Some(escape_text(value)),
+    }
+}
+
+// The first value of a property's parameter, with any quotes taken off
+pub fn property_param<'a>(property: &'a Property, name: &str) -> Option<&'a str> {
+    property
+        .params
+        .iter()
+        .flatten()
+        .find(|(param, _)| param.eq_ignore_ascii_case(name))
+        .and_then(|(_, values)| values.first())
+        .map(|value| value.trim_matches('"'))
+}
+
+// escapes a TEXT value, the iCalendar writer then folds long lines
+fn escape_text(value: &str) -> String {
+    value
+        .replace("\r\n", "\n")
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape_text(value: &str) -> String {
+    let mut output = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n' | 'N')) => {
+                chars.next();
+                output.push('\n');
+            }
+            ('\\', Some(escaped @ ('\\' | ';' | ','))) => {
+                chars.next();
+                output.push(escaped);
+            }
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+// splits a comma separated list of text values, leaving escaped commas alone
+fn split_list(value: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            ',' if !escaped => {
+                values.push(unescape_text(&value[start..index]));
+                start = index + 1;
+            }
+            _ => escaped = false,
+        }
+    }
+    values.push(unescape_text(&value[start..]));
+    values
+}
+
+fn merge_unique(mut values: Vec<String>, legacy: Vec<String>) -> Vec<String> {
+    for value in legacy {
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    values
+}
+
+// RELATED-TO defaults to a parent link when it has no RELTYPE
+fn is_parent_relation(property: &Property) -> bool {
+    match property_param(property, "RELTYPE") {
+        Some(reltype) => reltype.eq_ignore_ascii_case("PARENT"),
+        None => true,
+    }
+}
+
+// RFC 9253 links to tasks that must be finished first
+fn is_dependency_relation(property: &Property) -> bool {
+    property_param(property, "RELTYPE")
+        .is_some_and(|reltype| reltype.eq_ignore_ascii_case("DEPENDS-ON"))
 }
-#[derive(Debug, EnumString, Clone, Eq, PartialEq)]
-enum CompletionStatus {
-    Incomplete,
+
+fn is_legacy_category(category: &str) -> bool {
+    [
+        "CONTEXT ",
+        "AREA ",
+        "PROJECT ",
+        "MONEYNEEDED ",
+        "TIMEOFDAY ",
+        "WEATHER ",
+    ]
+    .iter()
+    .any(|prefix| category.starts_with(prefix))
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Status {
+    #[serde(alias = "Incomplete")]
+    NeedsAction,
+    InProcess,
     Completed(Option<DateTime<Utc>>),
+    Cancelled,
 }
 
-impl CompletionStatus {
-    pub fn from_kitchen_fridge(
+// kitchen-fridge only reports whether a task is completed, and keeps any
+// other STATUS among the extra parameters. Tasks written by earlier versions
+// are cancelled when completed with X-TASKS-STATUS:CANCELLED.
+impl Status {
+    fn from_kitchen_fridge(
         kitchen_fridge_completion_status: &kitchen_fridge::task::CompletionStatus,
+        status: Option<&str>,
     ) -> Self {
-        match kitchen_fridge_completion_status {
-            kitchen_fridge::task::CompletionStatus::Completed(data) => {
-                Self::Completed(data.clone())
+        match (kitchen_fridge_completion_status, status) {
+            (kitchen_fridge::task::CompletionStatus::Completed(_), Some("CANCELLED")) => {
+                Self::Cancelled
+            }
+            (kitchen_fridge::task::CompletionStatus::Completed(data), _) => Self::Completed(*data),
+            (kitchen_fridge::task::CompletionStatus::Uncompleted, Some("IN-PROCESS")) => {
+                Self::InProcess
             }
-            Incomplete => Self::Incomplete,
+            (kitchen_fridge::task::CompletionStatus::Uncompleted, Some("CANCELLED")) => {
+                Self::Cancelled
+            }
+            (kitchen_fridge::task::CompletionStatus::Uncompleted, _) => Self::NeedsAction,
         }
     }
 
-    pub fn to_kitchen_fridge(&self) -> kitchen_fridge::task::CompletionStatus {
+    fn to_kitchen_fridge(self) -> kitchen_fridge::task::CompletionStatus {
         match self {
-            CompletionStatus::Incomplete => kitchen_fridge::task::CompletionStatus::Uncompleted,
-            CompletionStatus::Completed(date_completed) => {
-                kitchen_fridge::task::CompletionStatus::Completed(date_completed.clone())
+            Status::NeedsAction | Status::InProcess | Status::Cancelled => {
+                kitchen_fridge::task::CompletionStatus::Uncompleted
+            }
+            Status::Completed(date_completed) => {
+                kitchen_fridge::task::CompletionStatus::Completed(date_completed)
             }
         }
     }
+
+    fn to_property_value(self) -> &'static str {
+        match self {
+            Status::NeedsAction => "NEEDS-ACTION",
+            Status::InProcess => "IN-PROCESS",
+            Status::Completed(_) => "COMPLETED",
+            Status::Cancelled => "CANCELLED",
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::NeedsAction => write!(f, "needs action"),
+            Status::InProcess => write!(f, "in process"),
+            Status::Completed(_) => write!(f, "completed"),
+            Status::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+// Ordered from most to least urgent, so sorting by it puts high priority first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+    None,
+}
+
+impl Priority {
+    // RFC 5545 reads 1-4 as high, 5 as medium and 6-9 as low
+    fn from_value(value: Option<u8>) -> Self {
+        match value {
+            Some(1..=4) => Priority::High,
+            Some(5) => Priority::Medium,
+            Some(6..=9) => Priority::Low,
+            _ => Priority::None,
+        }
+    }
+
+    fn to_value(self) -> Option<u8> {
+        match self {
+            Priority::High => Some(1),
+            Priority::Medium => Some(5),
+            Priority::Low => Some(9),
+            Priority::None => None,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, EnumString, Clone, Display)]
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::High => write!(f, "high"),
+            Priority::Medium => write!(f, "medium"),
+            Priority::Low => write!(f, "low"),
+            Priority::None => write!(f, "none"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, EnumString, Clone, Display, Serialize, Deserialize)]
 pub enum TimeOfDay {
     #[strum(serialize = "TIMEOFDAY  Morning")]
     Morning,
@@ -359,7 +1338,31 @@ pub enum TimeOfDay {
     #[strum(serialize = "TIMEOFDAY  Specific")]
     Specific(DateTime<Utc>),
 }
-#[derive(Debug, PartialEq, EnumString, Clone, Display)]
+impl TimeOfDay {
+    fn to_property_value(&self) -> String {
+        match self {
+            TimeOfDay::Morning => "MORNING".to_string(),
+            TimeOfDay::Midday => "MIDDAY".to_string(),
+            TimeOfDay::Afternoon => "AFTERNOON".to_string(),
+            TimeOfDay::Evening => "EVENING".to_string(),
+            TimeOfDay::Specific(time) => time.format("%Y%m%dT%H%M%SZ").to_string(),
+        }
+    }
+
+    fn from_property_value(value: &str) -> Option<Self> {
+        match value.to_uppercase().as_str() {
+            "MORNING" => Some(TimeOfDay::Morning),
+            "MIDDAY" => Some(TimeOfDay::Midday),
+            "AFTERNOON" => Some(TimeOfDay::Afternoon),
+            "EVENING" => Some(TimeOfDay::Evening),
+            value => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+                .ok()
+                .map(|time| TimeOfDay::Specific(DateTime::from_utc(time, Utc))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, EnumString, Clone, Display, Serialize, Deserialize)]
 pub enum Weather {
     #[strum(to_string = "WEATHER  Sunny")]
     Sunny,
@@ -371,6 +1374,29 @@ pub enum Weather {
     Windy,
 }
 
+impl Weather {
+    fn to_property_value(&self) -> String {
+        match self {
+            Weather::Sunny => "SUNNY",
+            Weather::Cloudy => "CLOUDY",
+            Weather::Rainy => "RAINY",
+            Weather::Windy => "WINDY",
+        }
+        .to_string()
+    }
+
+    fn from_property_value(value: &str) -> Option<Self> {
+        match value.to_uppercase().as_str() {
+            "SUNNY" => Some(Weather::Sunny),
+            "CLOUDY" => Some(Weather::Cloudy),
+            "RAINY" => Some(Weather::Rainy),
+            "WINDY" => Some(Weather::Windy),
+            _ => None,
+        }
+    }
+}
+
+#[allow(dead_code)]
 enum Context {
     Laptop,
     Home,
@@ -381,55 +1407,73 @@ enum Context {
     Youtube,
 }
 
-trait TaskItem {
+pub trait TaskItem {
+    fn get_properties(&self) -> Vec<&Property>;
     fn get_attribute_from_item(&self, attribute_name: &str) -> Option<String>;
-    fn get_date_from_item_attribute(&self, attribute_name: &str) -> Option<NaiveDateTime>;
-    fn get_parent_uuid(&self) -> Option<Uuid>;
+    fn get_attributes_from_item(&self, attribute_name: &str) -> Vec<String>;
+    fn get_date_from_item_attribute(&self, attribute_name: &str) -> Option<TaskDate>;
+    fn get_parent_uid(&self) -> Option<String>;
+    fn get_dependency_uids(&self) -> Vec<String>;
     fn get_tags(&self) -> Vec<String>;
     fn get_attribute_from_tag(&self, tag: &str) -> Vec<String>;
+    fn has_legacy_tags(&self) -> bool;
 }
 impl TaskItem for Item {
+    fn get_properties(&self) -> Vec<&Property> {
+        self.unwrap_task().extra_parameters().iter().collect()
+    }
+
     fn get_attribute_from_item(&self, attribute_name: &str) -> Option<String> {
-        self.unwrap_task()
-            .extra_parameters()
-            .iter()
+        self.get_properties()
+            .into_iter()
             .find(|x| x.name == attribute_name)?
             .value
-            .clone()
+            .as_deref()
+            .map(unescape_text)
+    }
+
+    fn get_attributes_from_item(&self, attribute_name: &str) -> Vec<String> {
+        self.get_properties()
+            .into_iter()
+            .filter(|x| x.name == attribute_name)
+            .flat_map(|x| x.value.as_deref())
+            .map(unescape_text)
+            .collect()
     }
 
-    fn get_date_from_item_attribute(&self, attribute_name: &str) -> Option<NaiveDateTime> {
-        NaiveDateTime::parse_from_str(
-            &self.get_attribute_from_item(attribute_name)?,
-            "%Y%m%dT%H%M%S",
-        )
-        .ok()
+    fn get_date_from_item_attribute(&self, attribute_name: &str) -> Option<TaskDate> {
+        self.get_properties()
+            .into_iter()
+            .find(|x| x.name == attribute_name)
+            .and_then(TaskDate::from_property)
+    }
+
+    fn get_parent_uid(&self) -> Option<String> {
+        let related = self
+            .get_properties()
+            .into_iter()
+            .find(|x| x.name == "RELATED-TO" && is_parent_relation(x))?
+            .value
+            .as_deref()?;
+        Some(related.trim().to_string())
     }
 
-    fn get_parent_uuid(&self) -> Option<Uuid> {
-        let related = &self.get_attribute_from_item("RELATED_TO")?;
-        Uuid::parse_str(&related).ok()
+    fn get_dependency_uids(&self) -> Vec<String> {
+        self.get_properties()
+            .into_iter()
+            .filter(|x| x.name == "RELATED-TO" && is_dependency_relation(x))
+            .filter_map(|x| x.value.as_deref())
+            .map(|related| related.trim().to_string())
+            .collect()
     }
 
     fn get_tags(&self) -> Vec<String> {
-        let categories_string: Option<String> = match self
-            .unwrap_task()
-            .extra_parameters()
+        self.get_properties()
             .into_iter()
             .filter(|x| x.name == "CATEGORIES")
-            .collect::<Vec<&Property>>()
-            .first()
-        {
-            Some(categories) => categories.value.clone(),
-            None => None,
-        };
-        match categories_string {
-            Some(categories_string) => {
-                let strs: Vec<&str> = categories_string.split(',').collect();
-                strs.into_iter().map(|s| s.to_string()).collect()
-            }
-            None => Vec::new(),
-        }
+            .flat_map(|x| x.value.as_deref().map(split_list).unwrap_or_default())
+            .map(|tag| tag.trim().to_string())
+            .collect()
     }
 
     fn get_attribute_from_tag(&self, tag: &str) -> Vec<String> {
@@ -440,4 +1484,393 @@ impl TaskItem for Item {
             .map(|t| t.to_string())
             .collect()
     }
+
+    fn has_legacy_tags(&self) -> bool {
+        self.get_tags().iter().any(|tag| is_legacy_category(tag))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::alarm::Anchor;
+    use chrono::NaiveDate;
+    use kitchen_fridge::ical::{build_from, parse};
+    use kitchen_fridge::item::SyncStatus;
+
+    const FOREIGN_TODO: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Other client//EN\r
+BEGIN:VTODO\r
+UID:foreign-task\r
+DTSTAMP:20240101T000000Z\r
+CREATED:20240101T000000Z\r
+LAST-MODIFIED:20240101T000000Z\r
+SUMMARY:Water the plants\r
+DESCRIPTION:Front\\, back\\nand side\r
+STATUS:IN-PROCESS\r
+DTSTART;TZID=Europe/Berlin:20240105T090000\r
+DUE;VALUE=DATE:20240106\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,TH;UNTIL=20240301T000000Z\r
+RELATED-TO:parent-elsewhere\r
+RELATED-TO;RELTYPE=DEPENDS-ON:dependency-elsewhere\r
+X-OTHER-CLIENT:kept\r
+BEGIN:VALARM\r
+ACTION:AUDIO\r
+TRIGGER;RELATED=END:-PT10M\r
+X-OTHER-ALARM:kept\r
+END:VALARM\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+
+    // as written before the X-TASKS- properties
+    pub(crate) const LEGACY_TODO: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//tasks//tasks//EN\r
+BEGIN:VTODO\r
+UID:legacy-task\r
+DTSTAMP:20220101T000000Z\r
+SUMMARY:Plant the tomatoes\r
+STATUS:NEEDS-ACTION\r
+CATEGORIES:WEATHER  Sunny,TIMEOFDAY  Morning,CONTEXT  garden, AREA  ho\r
+ me,PROJECT  vegetables,MONEYNEEDED  true,outdoors\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+
+    fn url() -> Url {
+        "https://example.com/calendar/task.ics".parse().unwrap()
+    }
+
+    fn read(ics: &str) -> Task {
+        let item = parse(ics, url(), SyncStatus::NotSynced).unwrap();
+        Task::from_item(item).unwrap()
+    }
+
+    fn write(task: &Task) -> String {
+        build_from(&task.to_item(&url(), &|task_id| task_id.to_string())).unwrap()
+    }
+
+    #[test]
+    fn reads_tasks_from_other_clients() {
+        let task = read(FOREIGN_TODO);
+        assert_eq!(task.uid(), "foreign-task");
+        assert_eq!(task.id, Task::id_for_uid("foreign-task"));
+        assert_eq!(task.name(), "Water the plants");
+    }
+
+    #[test]
+    fn keeps_uuid_uids_as_written() {
+        let uid = "8F3C2A4E-1B2D-4C5E-9F60-718293A4B5C6";
+        let task = read(&FOREIGN_TODO.replace("UID:foreign-task", &format!("UID:{}", uid)));
+        assert_eq!(task.id, Uuid::parse_str(uid).unwrap());
+        assert_eq!(task.uid(), uid);
+        let uid = task.id.to_string();
+        let task = read(&FOREIGN_TODO.replace("UID:foreign-task", &format!("UID:{}", uid)));
+        assert_eq!(task.uid(), uid);
+    }
+
+    #[test]
+    fn writes_tasks_from_other_clients_back_unchanged() {
+        let task = read(FOREIGN_TODO);
+        let ics = write(&task);
+        for line in [
+            "UID:foreign-task",
+            "DESCRIPTION:Front\\, back\\nand side",
+            "STATUS:IN-PROCESS",
+            "DTSTART;TZID=Europe/Berlin:20240105T090000",
+            "DUE;VALUE=DATE:20240106",
+            "RRULE:FREQ=WEEKLY;BYDAY=MO,TH;UNTIL=20240301T000000Z",
+            "RELATED-TO:parent-elsewhere",
+            "RELATED-TO;RELTYPE=DEPENDS-ON:dependency-elsewhere",
+            "X-OTHER-CLIENT:kept",
+            "BEGIN:VALARM",
+            "ACTION:AUDIO",
+            "TRIGGER;RELATED=END:-PT10M",
+            "X-OTHER-ALARM:kept",
+        ] {
+            assert!(ics.contains(line), "{} is missing from\n{}", line, ics);
+        }
+        assert!(!ics.contains("NEEDS-ACTION"));
+        assert_eq!(read(&ics), task);
+    }
+
+    #[test]
+    fn writes_alarms_as_valarm_components() {
+        let alarm = Alarm::Relative(Anchor::Due, -900);
+        let task = Task::new("Call back".to_owned()).add_alarm(alarm.clone());
+        let ics = write(&task);
+        assert!(
+            ics.contains(
+                "BEGIN:VALARM\r\nACTION:DISPLAY\r\nDESCRIPTION:Call back\r\n\
+                 TRIGGER;RELATED=END:-PT15M\r\nEND:VALARM\r\n"
+            ),
+            "{}",
+            ics
+        );
+        let task = read(&ics);
+        assert_eq!(task.alarms(), &[alarm]);
+        // written again from the alarm, so that it follows the task's name
+        assert!(task.other_alarms.is_empty());
+        assert!(write(&task.set_name("Call back today".to_owned()))
+            .contains("DESCRIPTION:Call back today\r\nTRIGGER"));
+    }
+
+    #[test]
+    fn removing_an_alarm_from_another_client_drops_its_component() {
+        let task = read(FOREIGN_TODO);
+        assert_eq!(task.alarms(), &[Alarm::Relative(Anchor::Due, -600)]);
+        let ics = write(&task.remove_alarm(&Alarm::Relative(Anchor::Due, -600)));
+        assert!(!ics.contains("VALARM"));
+    }
+
+    #[test]
+    fn splits_lists_on_unescaped_commas() {
+        assert_eq!(split_list("a,b\\,c,d"), vec!["a", "b,c", "d"]);
+        assert_eq!(split_list("one"), vec!["one"]);
+        assert_eq!(split_list("a\\\\,b"), vec!["a\\", "b"]);
+    }
+
+    #[test]
+    fn reads_parent_links_by_uid() {
+        let task = read(FOREIGN_TODO);
+        assert_eq!(
+            task.parent_task(),
+            Some(Task::id_for_uid("parent-elsewhere"))
+        );
+    }
+
+    #[test]
+    fn escapes_text() {
+        let text = "a, b; c\\d\nnext";
+        assert_eq!(escape_text(text), r"a\, b\; c\\d\nnext");
+        assert_eq!(unescape_text(&escape_text(text)), text);
+        assert_eq!(unescape_text("line\\Nbreak"), "line\nbreak");
+    }
+
+    #[test]
+    fn reads_descriptions() {
+        let task = read(FOREIGN_TODO);
+        assert_eq!(task.description(), Some("Front, back\nand side"));
+    }
+
+    #[test]
+    fn reads_until_in_its_own_form() {
+        let task = read(FOREIGN_TODO);
+        assert!(matches!(
+            task.recurrence()
+                .and_then(|recurrence| recurrence.until.as_ref()),
+            Some(TaskDate::Utc(_))
+        ));
+    }
+
+    #[test]
+    fn next_instance_moves_on_until_the_rule_ends() {
+        let task = read(FOREIGN_TODO)
+            .set_start_date(None)
+            .set_due_date(Some(TaskDate::AllDay(
+                NaiveDate::from_ymd_opt(2024, 2, 26).unwrap(),
+            )))
+            .mark_complete();
+        let next = task.next_instance(Utc::now()).unwrap();
+        assert_eq!(
+            next.due(),
+            Some(&TaskDate::AllDay(
+                NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+            ))
+        );
+        assert_eq!(next.status(), Status::NeedsAction);
+        // the Monday after is past UNTIL
+        assert_eq!(next.next_instance(Utc::now()), None);
+    }
+
+    #[test]
+    fn status_round_trips() {
+        let task = read(FOREIGN_TODO);
+        assert_eq!(task.status(), Status::InProcess);
+        for task in [
+            task.mark_incomplete(),
+            task.mark_in_process(),
+            task.mark_cancelled(),
+            task.mark_complete(),
+        ] {
+            let status = read(&write(&task)).status();
+            match task.status() {
+                // the completion time is written in whole seconds
+                Status::Completed(_) => assert!(matches!(status, Status::Completed(Some(_)))),
+                expected => assert_eq!(status, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_zoned_and_all_day_dates() {
+        let task = read(FOREIGN_TODO);
+        assert_eq!(
+            task.start_date(),
+            Some(&TaskDate::Zoned(
+                NaiveDateTime::parse_from_str("20240105T090000", "%Y%m%dT%H%M%S").unwrap(),
+                "Europe/Berlin".to_string()
+            ))
+        );
+        assert_eq!(
+            task.due(),
+            Some(&TaskDate::AllDay(
+                NaiveDate::from_ymd_opt(2024, 1, 6).unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn adding_a_value_twice_keeps_one() {
+        let task = Task::new("twice".to_string());
+        let task = task
+            .add_context("x".to_string())
+            .add_context("x".to_string());
+        let task = task.add_area("x".to_string()).add_area("x".to_string());
+        let task = task
+            .add_project("x".to_string())
+            .add_project("x".to_string());
+        let task = task.add_tag("x".to_string()).add_tag("x".to_string());
+        assert_eq!(task.contexts(), ["x"]);
+        assert_eq!(task.areas(), ["x"]);
+        assert_eq!(task.projects(), ["x"]);
+        assert_eq!(task.tags(), ["x"]);
+    }
+
+    #[test]
+    fn completing_stops_the_timer() {
+        let started = Utc::now() - chrono::Duration::minutes(30);
+        let task = Task::new("timed".to_string()).start_timer(started);
+        let completed = task.mark_complete();
+        assert_eq!(completed.timer_started(), None);
+        assert!(completed.time_spent(Utc::now()) >= 1800);
+    }
+
+    #[test]
+    fn next_instance_starts_without_time_spent() {
+        let task = read(FOREIGN_TODO)
+            .start_timer(Utc::now() - chrono::Duration::minutes(5))
+            .mark_complete();
+        assert!(task.time_spent(Utc::now()) > 0);
+        let next = task.next_instance(Utc::now()).unwrap();
+        assert_eq!(next.timer_started(), None);
+        assert_eq!(next.time_spent(Utc::now()), 0);
+    }
+
+    #[test]
+    fn reads_dependencies_by_uid() {
+        let task = read(FOREIGN_TODO);
+        assert_eq!(
+            task.depends_on(),
+            &[Task::id_for_uid("dependency-elsewhere")]
+        );
+    }
+
+    #[test]
+    fn reads_legacy_categories() {
+        let task = read(LEGACY_TODO);
+        assert_eq!(task.contexts(), &["garden"]);
+        assert_eq!(task.areas(), &["home"]);
+        assert_eq!(task.projects(), &["vegetables"]);
+        assert!(task.money_needed());
+        assert_eq!(task.time_of_day(), Some(&TimeOfDay::Morning));
+        assert_eq!(task.weather(), Some(&Weather::Sunny));
+        assert_eq!(task.tags(), &["outdoors"]);
+    }
+
+    #[test]
+    fn writes_legacy_categories_as_properties() {
+        let task = read(LEGACY_TODO);
+        let item = task.to_item(&url(), &|task_id| task_id.to_string());
+        assert!(!item.has_legacy_tags());
+        let ics = build_from(&item).unwrap();
+        for line in [
+            "X-TASKS-CONTEXT:garden",
+            "X-TASKS-AREA:home",
+            "X-TASKS-PROJECT:vegetables",
+            "X-TASKS-MONEY-NEEDED:TRUE",
+            "CATEGORIES:outdoors",
+        ] {
+            assert!(ics.contains(line), "{} is missing from\n{}", line, ics);
+        }
+        assert_eq!(read(&ics), task);
+    }
+
+    fn mapping() -> TagMapping {
+        TagMapping {
+            contexts: [("@home".to_string(), "home".to_string())].into(),
+            areas: [("job".to_string(), "work".to_string())].into(),
+        }
+    }
+
+    #[test]
+    fn maps_tags_to_contexts_and_areas() {
+        let task = Task::new("Tidy up".to_string())
+            .add_context("home".to_string())
+            .add_tag("@home".to_string())
+            .add_tag("job".to_string())
+            .add_tag("misc".to_string());
+        let mapped = task.with_mapped_tags(&mapping());
+        assert_eq!(mapped.contexts(), &["home"]);
+        assert_eq!(mapped.areas(), &["work"]);
+        assert_eq!(mapped.tags(), &["misc"]);
+    }
+
+    #[test]
+    fn unmaps_contexts_and_areas_to_their_tags() {
+        let task = Task::new("Tidy up".to_string())
+            .add_context("home".to_string())
+            .add_context("phone".to_string())
+            .add_area("work".to_string())
+            .add_tag("misc".to_string());
+        let unmapped = task.with_unmapped_tags(&mapping());
+        let mut tags = unmapped.tags().to_vec();
+        tags.sort();
+        assert_eq!(tags, vec!["@home", "job", "misc"]);
+        // the contexts and areas stay, for clients that read them
+        assert_eq!(unmapped.contexts(), task.contexts());
+        assert_eq!(unmapped.with_mapped_tags(&mapping()), task);
+    }
+
+    #[test]
+    fn reads_priorities_the_rfc_5545_way() {
+        let priorities: Vec<Priority> = (0..=9)
+            .map(|value| Priority::from_value(Some(value)))
+            .collect();
+        assert_eq!(
+            priorities,
+            vec![
+                Priority::None,
+                Priority::High,
+                Priority::High,
+                Priority::High,
+                Priority::High,
+                Priority::Medium,
+                Priority::Low,
+                Priority::Low,
+                Priority::Low,
+                Priority::Low,
+            ]
+        );
+        assert_eq!(Priority::from_value(None), Priority::None);
+        for priority in [
+            Priority::High,
+            Priority::Medium,
+            Priority::Low,
+            Priority::None,
+        ] {
+            assert_eq!(Priority::from_value(priority.to_value()), priority);
+        }
+    }
+
+    #[test]
+    fn keeps_priorities_from_other_clients_as_written() {
+        let task =
+            read(&FOREIGN_TODO.replace("STATUS:IN-PROCESS", "STATUS:IN-PROCESS\r\nPRIORITY:3"));
+        assert_eq!(task.priority(), Priority::High);
+        assert!(write(&task).contains("PRIORITY:3\r\n"));
+        assert!(write(&task.set_priority(Priority::Low)).contains("PRIORITY:9\r\n"));
+    }
 }
@@ -1,3 +1,4 @@
+#[allow(dead_code)]
 pub enum Context {
     Laptop,
     Phone,
@@ -5,14 +6,15 @@ pub enum Context {
     Errands,
     Home,
     SocialMedia,
-    YouTube
+    YouTube,
 }
 
+#[allow(dead_code)]
 pub enum Property {
     Context(Context),
     Time,
     Status,
     Area,
     Project,
-    Weather
+    Weather,
 }
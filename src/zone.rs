@@ -0,0 +1,467 @@
+use crate::error::{Error, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc, Weekday};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+// A time zone read from the system's TZif files, as named by a TZID or by
+// the timezone setting of a profile.
+#[derive(Debug)]
+pub struct Zone {
+    name: String,
+    initial_offset: i32,
+    // UTC seconds at which the offset changes, with the offset from then on
+    transitions: Vec<(i64, i32)>,
+    // the POSIX rule for times after the last transition
+    rule: Option<Rule>,
+}
+
+impl Zone {
+    pub fn utc() -> Self {
+        Self {
+            name: "UTC".to_string(),
+            initial_offset: 0,
+            transitions: Vec::new(),
+            rule: None,
+        }
+    }
+
+    pub fn load(name: &str) -> Result<Arc<Zone>> {
+        static ZONES: OnceLock<Mutex<HashMap<String, Arc<Zone>>>> = OnceLock::new();
+        let mut zones = ZONES.get_or_init(Default::default).lock()?;
+        if let Some(zone) = zones.get(name) {
+            return Ok(zone.clone());
+        }
+        let zone = match name {
+            "UTC" | "Etc/UTC" | "Z" => Zone::utc(),
+            _ => Zone::read(name)?,
+        };
+        let zone = Arc::new(zone);
+        zones.insert(name.to_string(), zone.clone());
+        Ok(zone)
+    }
+
+    fn read(name: &str) -> Result<Self> {
+        let unknown = || Error::Config(format!("unknown time zone {}", name));
+        if name.is_empty() || name.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(unknown());
+        }
+        let zoneinfo = std::env::var_os("TZDIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(ZONEINFO));
+        let data = std::fs::read(zoneinfo.join(name)).map_err(|_| unknown())?;
+        let (initial_offset, transitions, rule) = parse_tzif(&data).ok_or_else(unknown)?;
+        Ok(Self {
+            name: name.to_string(),
+            initial_offset,
+            transitions,
+            rule,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn offset_at(&self, utc: i64) -> i32 {
+        let rule_or = |offset: i32| {
+            self.rule
+                .as_ref()
+                .map_or(offset, |rule| rule.offset_at(utc))
+        };
+        match self.transitions.iter().rposition(|(at, _)| *at <= utc) {
+            Some(index) if index + 1 < self.transitions.len() => self.transitions[index].1,
+            Some(index) => rule_or(self.transitions[index].1),
+            None if self.transitions.is_empty() => rule_or(self.initial_offset),
+            None => self.initial_offset,
+        }
+    }
+
+    pub fn to_local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        time.naive_utc() + Duration::seconds(self.offset_at(time.timestamp()) as i64)
+    }
+
+    // Times skipped or repeated by a change of offset resolve to the offset
+    // in force just before the change.
+    pub fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let guess = local.timestamp();
+        let before = self.offset_at(guess - self.offset_at(guess) as i64 - 86400);
+        let offset = match self.offset_at(guess - before as i64) {
+            after if self.offset_at(guess - after as i64) == after => after,
+            _ => before,
+        };
+        DateTime::from_utc(local - Duration::seconds(offset as i64), Utc)
+    }
+}
+
+type Tzif = (i32, Vec<(i64, i32)>, Option<Rule>);
+
+fn parse_tzif(data: &[u8]) -> Option<Tzif> {
+    let header = |data: &[u8]| -> Option<[usize; 6]> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let mut counts = [0; 6];
+        for (index, count) in counts.iter_mut().enumerate() {
+            let start = 20 + index * 4;
+            *count = u32::from_be_bytes(data.get(start..start + 4)?.try_into().ok()?) as usize;
+        }
+        Some(counts)
+    };
+    let block_length = |counts: [usize; 6], time_size: usize| {
+        let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = counts;
+        timecnt * time_size
+            + timecnt
+            + typecnt * 6
+            + charcnt
+            + leapcnt * (time_size + 4)
+            + isstdcnt
+            + isutcnt
+    };
+    let counts = header(data)?;
+    let version = *data.get(4)?;
+    let (data, counts, time_size) = match version {
+        0 => (data, counts, 4),
+        _ => {
+            let data = data.get(44 + block_length(counts, 4)..)?;
+            (data, header(data)?, 8)
+        }
+    };
+    let [_, _, _, timecnt, typecnt, _] = counts;
+    let body = data.get(44..)?;
+    let times = body.get(..timecnt * time_size)?;
+    let indices = body.get(timecnt * time_size..timecnt * (time_size + 1))?;
+    let types = body.get(timecnt * (time_size + 1)..timecnt * (time_size + 1) + typecnt * 6)?;
+    let offset_of = |index: usize| -> Option<i32> {
+        Some(i32::from_be_bytes(
+            types.get(index * 6..index * 6 + 4)?.try_into().ok()?,
+        ))
+    };
+    let mut transitions = Vec::new();
+    for index in 0..timecnt {
+        let time = times.get(index * time_size..(index + 1) * time_size)?;
+        let time = match time_size {
+            4 => i32::from_be_bytes(time.try_into().ok()?) as i64,
+            _ => i64::from_be_bytes(time.try_into().ok()?),
+        };
+        transitions.push((time, offset_of(*indices.get(index)? as usize)?));
+    }
+    let rule = match version {
+        0 => None,
+        _ => {
+            let footer = data.get(44 + block_length(counts, 8)..)?;
+            let footer = std::str::from_utf8(footer).ok()?;
+            Rule::parse(footer.trim())
+        }
+    };
+    Some((offset_of(0)?, transitions, rule))
+}
+
+// A POSIX TZ string such as "AEST-10AEDT,M10.1.0,M4.1.0/3"
+#[derive(Debug)]
+struct Rule {
+    standard: i32,
+    daylight: Option<(i32, Transition, Transition)>,
+}
+
+// month, week (5 being the last), day of the week and local time in seconds
+#[derive(Debug)]
+struct Transition(u32, u32, Weekday, i64);
+
+impl Rule {
+    fn parse(rule: &str) -> Option<Self> {
+        let rest = skip_name(rule)?;
+        let (standard, rest) = parse_offset(rest)?;
+        if rest.is_empty() {
+            return Some(Self {
+                standard,
+                daylight: None,
+            });
+        }
+        let rest = skip_name(rest)?;
+        let (daylight, rest) = match rest.starts_with(',') {
+            true => (standard + 3600, rest),
+            false => parse_offset(rest)?,
+        };
+        let mut parts = rest.strip_prefix(',')?.split(',');
+        let start = parse_transition(parts.next()?)?;
+        let end = parse_transition(parts.next()?)?;
+        Some(Self {
+            standard,
+            daylight: Some((daylight, start, end)),
+        })
+    }
+
+    fn offset_at(&self, utc: i64) -> i32 {
+        let (daylight, start, end) = match &self.daylight {
+            Some(daylight) => daylight,
+            None => return self.standard,
+        };
+        let year = NaiveDateTime::from_timestamp_opt(utc + self.standard as i64, 0)
+            .map(|time| time.year())
+            .unwrap_or(1970);
+        let (start, end) = match (
+            start
+                .local_time(year)
+                .map(|time| time - self.standard as i64),
+            end.local_time(year).map(|time| time - *daylight as i64),
+        ) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return self.standard,
+        };
+        let in_daylight = match start < end {
+            true => start <= utc && utc < end,
+            // the southern hemisphere, where daylight time spans the new year
+            false => !(end <= utc && utc < start),
+        };
+        match in_daylight {
+            true => *daylight,
+            false => self.standard,
+        }
+    }
+}
+
+impl Transition {
+    fn local_time(&self, year: i32) -> Option<i64> {
+        let Transition(month, week, weekday, time) = self;
+        let first = NaiveDate::from_ymd_opt(year, *month, 1)?;
+        let first_day = (7 + weekday.num_days_from_sunday() as i64
+            - first.weekday().num_days_from_sunday() as i64)
+            % 7;
+        let mut date = first + Duration::days(first_day + 7 * (*week as i64 - 1));
+        while date.month() != *month {
+            date -= Duration::days(7);
+        }
+        Some(date.and_hms_opt(0, 0, 0)?.timestamp() + time)
+    }
+}
+
+fn skip_name(rule: &str) -> Option<&str> {
+    match rule.strip_prefix('<') {
+        Some(rest) => Some(&rest[rest.find('>')? + 1..]),
+        None => {
+            let length = rule
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rule.len());
+            (length >= 3).then(|| &rule[length..])
+        }
+    }
+}
+
+// POSIX offsets count west of Greenwich, so they are negated here
+fn parse_offset(rule: &str) -> Option<(i32, &str)> {
+    let length = rule
+        .find(|c: char| !(c.is_ascii_digit() || "+-:".contains(c)))
+        .unwrap_or(rule.len());
+    let seconds = parse_time(&rule[..length])?;
+    Some((-seconds as i32, &rule[length..]))
+}
+
+fn parse_time(time: &str) -> Option<i64> {
+    let (sign, time) = match time.strip_prefix('-') {
+        Some(time) => (-1, time),
+        None => (1, time.strip_prefix('+').unwrap_or(time)),
+    };
+    let mut seconds = 0;
+    for (index, part) in time.split(':').enumerate() {
+        seconds += part.parse::<i64>().ok()? * [3600, 60, 1].get(index)?;
+    }
+    Some(sign * seconds)
+}
+
+fn parse_transition(transition: &str) -> Option<Transition> {
+    let (date, time) = match transition.split_once('/') {
+        Some((date, time)) => (date, parse_time(time)?),
+        None => (transition, 7200),
+    };
+    let mut parts = date.strip_prefix('M')?.split('.');
+    let month = parts.next()?.parse().ok()?;
+    let week = parts.next()?.parse().ok()?;
+    let weekday = match parts.next()?.parse::<u32>().ok()? {
+        0 => Weekday::Sun,
+        1 => Weekday::Mon,
+        2 => Weekday::Tue,
+        3 => Weekday::Wed,
+        4 => Weekday::Thu,
+        5 => Weekday::Fri,
+        6 => Weekday::Sat,
+        _ => return None,
+    };
+    Some(Transition(month, week, weekday, time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::from_utc(local(time), Utc)
+    }
+
+    fn local(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    // A TZif block of the given version with one type per offset, and
+    // transitions given as UTC times and type indices
+    fn tzif_block(version: u8, offsets: &[i32], transitions: &[(&str, u8)]) -> Vec<u8> {
+        let time_size = if version == 0 { 4 } else { 8 };
+        let mut data = b"TZif".to_vec();
+        data.push(version);
+        data.extend([0; 15]);
+        for count in [0, 0, 0, transitions.len(), offsets.len(), 0] {
+            data.extend((count as u32).to_be_bytes());
+        }
+        for (time, _) in transitions {
+            let time = utc(time).timestamp();
+            match time_size {
+                4 => data.extend((time as i32).to_be_bytes()),
+                _ => data.extend(time.to_be_bytes()),
+            }
+        }
+        data.extend(transitions.iter().map(|(_, index)| *index));
+        for offset in offsets {
+            data.extend(offset.to_be_bytes());
+            data.extend([0, 0]);
+        }
+        data
+    }
+
+    fn berlin_2021() -> Zone {
+        Zone {
+            name: "Europe/Berlin".to_string(),
+            initial_offset: 3600,
+            transitions: vec![
+                (utc("2021-03-28 01:00").timestamp(), 7200),
+                (utc("2021-10-31 01:00").timestamp(), 3600),
+            ],
+            rule: None,
+        }
+    }
+
+    #[test]
+    fn reads_version_1_files() {
+        let data = tzif_block(
+            0,
+            &[3600, 7200],
+            &[("2021-03-28 01:00", 1), ("2021-10-31 01:00", 0)],
+        );
+        let (initial_offset, transitions, rule) = parse_tzif(&data).unwrap();
+        assert_eq!(initial_offset, 3600);
+        assert_eq!(
+            transitions,
+            vec![
+                (utc("2021-03-28 01:00").timestamp(), 7200),
+                (utc("2021-10-31 01:00").timestamp(), 3600),
+            ]
+        );
+        assert!(rule.is_none());
+    }
+
+    #[test]
+    fn reads_the_rule_of_version_2_files() {
+        let mut data = tzif_block(2, &[], &[]);
+        data.extend(tzif_block(
+            2,
+            &[-18000, -14400],
+            &[("2007-03-11 07:00", 1), ("2007-11-04 06:00", 0)],
+        ));
+        data.extend(b"\nEST5EDT,M3.2.0,M11.1.0\n");
+        let (initial_offset, transitions, rule) = parse_tzif(&data).unwrap();
+        assert_eq!(initial_offset, -18000);
+        assert_eq!(transitions.len(), 2);
+        let rule = rule.unwrap();
+        assert_eq!(rule.standard, -18000);
+        assert_eq!(rule.daylight.map(|(offset, _, _)| offset), Some(-14400));
+    }
+
+    #[test]
+    fn skipped_times_take_the_offset_before() {
+        let zone = berlin_2021();
+        assert_eq!(
+            zone.to_utc(local("2021-03-28 02:30")),
+            utc("2021-03-28 01:30")
+        );
+        assert_eq!(
+            zone.to_utc(local("2021-03-28 03:30")),
+            utc("2021-03-28 01:30")
+        );
+        assert_eq!(
+            zone.to_local(utc("2021-03-28 00:59")),
+            local("2021-03-28 01:59")
+        );
+        assert_eq!(
+            zone.to_local(utc("2021-03-28 01:00")),
+            local("2021-03-28 03:00")
+        );
+    }
+
+    #[test]
+    fn repeated_times_take_the_offset_before() {
+        let zone = berlin_2021();
+        assert_eq!(
+            zone.to_utc(local("2021-10-31 02:30")),
+            utc("2021-10-31 00:30")
+        );
+        assert_eq!(
+            zone.to_utc(local("2021-10-31 03:30")),
+            utc("2021-10-31 02:30")
+        );
+        assert_eq!(
+            zone.to_local(utc("2021-10-31 01:30")),
+            local("2021-10-31 02:30")
+        );
+    }
+
+    #[test]
+    fn southern_hemisphere_rules_span_the_new_year() {
+        let rule = Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(rule.standard, 36000);
+        assert_eq!(rule.offset_at(utc("2022-01-15 00:00").timestamp()), 39600);
+        assert_eq!(rule.offset_at(utc("2022-07-15 00:00").timestamp()), 36000);
+        assert_eq!(rule.offset_at(utc("2022-12-31 23:00").timestamp()), 39600);
+        // daylight time ends at 03:00 local on the first Sunday of April
+        assert_eq!(rule.offset_at(utc("2022-04-02 15:59").timestamp()), 39600);
+        assert_eq!(rule.offset_at(utc("2022-04-02 16:00").timestamp()), 36000);
+        // and starts at 02:00 local on the first Sunday of October
+        assert_eq!(rule.offset_at(utc("2022-10-01 15:59").timestamp()), 36000);
+        assert_eq!(rule.offset_at(utc("2022-10-01 16:00").timestamp()), 39600);
+    }
+
+    #[test]
+    fn parses_quoted_names_and_minutes() {
+        let rule = Rule::parse("<+0330>-3:30").unwrap();
+        assert_eq!(rule.standard, 12600);
+        assert!(rule.daylight.is_none());
+    }
+
+    #[test]
+    fn rule_applies_after_the_last_transition() {
+        let zone = Zone {
+            name: "America/New_York".to_string(),
+            initial_offset: -18000,
+            transitions: vec![
+                (utc("2007-03-11 07:00").timestamp(), -14400),
+                (utc("2007-11-04 06:00").timestamp(), -18000),
+            ],
+            rule: Rule::parse("EST5EDT,M3.2.0,M11.1.0"),
+        };
+        assert_eq!(zone.offset_at(utc("2007-07-01 12:00").timestamp()), -14400);
+        assert_eq!(zone.offset_at(utc("2040-01-01 12:00").timestamp()), -18000);
+        assert_eq!(zone.offset_at(utc("2040-07-01 12:00").timestamp()), -14400);
+        assert_eq!(
+            zone.to_utc(local("2040-07-01 12:00")),
+            utc("2040-07-01 16:00")
+        );
+    }
+
+    #[test]
+    fn without_a_rule_the_last_offset_stays() {
+        let zone = berlin_2021();
+        assert_eq!(zone.offset_at(utc("2040-07-01 12:00").timestamp()), 3600);
+    }
+}